@@ -0,0 +1,164 @@
+//! `rt_rs::vec3::Vec3` against the `[f64; 3]` + `SliceOp` vectors it
+//! replaced, on the math of a sphere hit followed by a scatter.
+//!
+//! `cargo bench --bench vec3`, add `--features simd` for the SIMD backend.
+
+use std::hint::black_box;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use rt_rs::{util, vec3::Vec3d as Vec3};
+
+/// The previous implementation, kept as the baseline.
+mod array {
+    pub type Vec3 = [f64; 3];
+
+    pub trait SliceOp {
+        fn len_squared(&self) -> f64;
+        fn dot(self, rhs: Vec3) -> f64;
+        fn unit_vec(self) -> Vec3;
+        fn reflect(self, n: Vec3) -> Vec3;
+        fn refract(self, n: Vec3, etai_over_etat: f64) -> Vec3;
+        fn neg(&self) -> Vec3;
+        fn add(self, rhs: Vec3) -> Vec3;
+        fn sub(self, rhs: Vec3) -> Vec3;
+        fn mul_f(self, rhs: f64) -> Vec3;
+        fn div_f(self, rhs: f64) -> Vec3;
+    }
+
+    impl SliceOp for Vec3 {
+        fn len_squared(&self) -> f64 {
+            self[0] * self[0] + self[1] * self[1] + self[2] * self[2]
+        }
+
+        fn dot(self, rhs: Vec3) -> f64 {
+            self[0] * rhs[0] + self[1] * rhs[1] + self[2] * rhs[2]
+        }
+
+        fn unit_vec(self) -> Vec3 {
+            let len = self.len_squared().sqrt();
+            self.div_f(len)
+        }
+
+        fn reflect(self, n: Vec3) -> Vec3 {
+            self.sub(n.mul_f(2.0 * self.dot(n)))
+        }
+
+        fn refract(self, n: Vec3, etai_over_etat: f64) -> Vec3 {
+            let cos_theta = self.neg().dot(n).min(1.0);
+            let r_out_perp = self.add(n.mul_f(cos_theta)).mul_f(etai_over_etat);
+            let r_out_parallel = n.mul_f(-(1.0 - r_out_perp.len_squared()).abs().sqrt());
+            r_out_perp.add(r_out_parallel)
+        }
+
+        fn neg(&self) -> Vec3 {
+            [-self[0], -self[1], -self[2]]
+        }
+
+        fn add(self, rhs: Vec3) -> Vec3 {
+            [self[0] + rhs[0], self[1] + rhs[1], self[2] + rhs[2]]
+        }
+
+        fn sub(self, rhs: Vec3) -> Vec3 {
+            [self[0] - rhs[0], self[1] - rhs[1], self[2] - rhs[2]]
+        }
+
+        fn mul_f(self, rhs: f64) -> Vec3 {
+            [self[0] * rhs, self[1] * rhs, self[2] * rhs]
+        }
+
+        fn div_f(self, rhs: f64) -> Vec3 {
+            self.mul_f(1.0 / rhs)
+        }
+    }
+}
+
+const RAYS: usize = 4096;
+
+/// Deterministic ray origins and directions around a unit sphere at the
+/// origin.
+fn rays() -> Vec<([f64; 3], [f64; 3])> {
+    let mut state = 0;
+    let mut next = || {
+        state += 1;
+        (util::mix64(state) >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+    };
+    (0..RAYS)
+        .map(|_| {
+            let origin = [next() * 0.5, next() * 0.5, 3.0];
+            let direction = [next() * 0.3, next() * 0.3, -1.0];
+            (origin, direction)
+        })
+        .collect()
+}
+
+fn hit_and_scatter_array(rays: &[([f64; 3], [f64; 3])]) -> f64 {
+    use array::SliceOp;
+
+    let center = [0.0, 0.0, 0.0];
+    let radius = 1.0;
+    let mut acc = 0.0;
+    for (origin, direction) in rays {
+        let oc = center.sub(*origin);
+        let a = direction.len_squared();
+        let h = direction.dot(oc);
+        let c = oc.len_squared() - radius * radius;
+        let discriminant = h * h - a * c;
+        if discriminant < 0.0 {
+            continue;
+        }
+        let t = (h - discriminant.sqrt()) / a;
+        let p = origin.add(direction.mul_f(t));
+        let normal = p.sub(center).div_f(radius);
+
+        let unit = direction.unit_vec();
+        let reflected = unit.reflect(normal);
+        let refracted = unit.refract(normal, 1.0 / 1.5);
+        acc += reflected.dot(refracted);
+    }
+    acc
+}
+
+fn hit_and_scatter_vec3(rays: &[(Vec3, Vec3)]) -> f64 {
+    let center = Vec3::ZERO;
+    let radius = 1.0;
+    let mut acc = 0.0;
+    for &(origin, direction) in rays {
+        let oc = center - origin;
+        let a = direction.len_squared();
+        let h = direction.dot(oc);
+        let c = oc.len_squared() - radius * radius;
+        let discriminant = h * h - a * c;
+        if discriminant < 0.0 {
+            continue;
+        }
+        let t = (h - discriminant.sqrt()) / a;
+        let p = origin + direction * t;
+        let normal = (p - center) / radius;
+
+        let unit = direction.unit_vec();
+        let reflected = unit.reflect(normal);
+        let refracted = unit.refract(normal, 1.0 / 1.5);
+        acc += reflected.dot(refracted);
+    }
+    acc
+}
+
+fn bench(c: &mut Criterion) {
+    let array_rays = rays();
+    let vec3_rays: Vec<_> = array_rays
+        .iter()
+        .map(|(o, d)| (Vec3::from(*o), Vec3::from(*d)))
+        .collect();
+
+    let mut group = c.benchmark_group("hit_and_scatter");
+    group.bench_function("array", |b| {
+        b.iter(|| hit_and_scatter_array(black_box(&array_rays)))
+    });
+    group.bench_function("vec3", |b| {
+        b.iter(|| hit_and_scatter_vec3(black_box(&vec3_rays)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
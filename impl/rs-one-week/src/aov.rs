@@ -0,0 +1,238 @@
+use std::{
+    io::{self, Read, Write},
+    str::FromStr,
+};
+
+use crate::{
+    checkpoint,
+    color::Color,
+    output::{ExrLayer, ExrSamples},
+    vec3::Vec3d,
+};
+
+/// Arbitrary output variables, extra per-pixel data about the first surface
+/// seen through each pixel.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AovKind {
+    Albedo,
+    /// shading normal, facing the camera
+    Normal,
+    /// distance along the camera's viewing direction
+    Depth,
+    /// world space position
+    Position,
+    MaterialId,
+    /// index of the object in the scene plus one, 0 is the background
+    ObjectId,
+}
+
+impl AovKind {
+    pub const ALL: [AovKind; 6] = [
+        Self::Albedo,
+        Self::Normal,
+        Self::Depth,
+        Self::Position,
+        Self::MaterialId,
+        Self::ObjectId,
+    ];
+
+    /// name of the layer or file suffix the AOV is written to
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Albedo => "albedo",
+            Self::Normal => "normal",
+            Self::Depth => "depth",
+            Self::Position => "position",
+            Self::MaterialId => "material_id",
+            Self::ObjectId => "object_id",
+        }
+    }
+
+    /// Parse a comma separated list such as `albedo,normal` or `all`.
+    pub fn parse_list(s: &str) -> Result<Vec<Self>, String> {
+        if s.eq_ignore_ascii_case("all") {
+            return Ok(Self::ALL.to_vec());
+        }
+        s.split(',').map(|name| name.trim().parse()).collect()
+    }
+}
+
+impl FromStr for AovKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("Unknown AOV: {s}"))
+    }
+}
+
+/// What a single camera ray saw at its first hit.
+#[derive(Clone, Copy, Debug)]
+pub struct AovSample {
+    pub albedo: Color,
+    pub normal: Vec3d,
+    pub depth: f64,
+    pub position: Vec3d,
+    pub material_id: u32,
+    pub object_id: u32,
+}
+
+impl AovSample {
+    /// a camera ray that escaped to the sky
+    pub fn background(sky: Color) -> Self {
+        Self {
+            albedo: sky,
+            normal: Vec3d::ZERO,
+            depth: f64::INFINITY,
+            position: Vec3d::ZERO,
+            material_id: 0,
+            object_id: 0,
+        }
+    }
+}
+
+/// Accumulated AOV samples of one pixel.
+///
+/// Continuous values are averaged over all samples, the ids can't be
+/// blended and come from the first sample.
+#[derive(Clone, Copy, Debug)]
+pub struct AovPixel {
+    albedo: Color,
+    normal: Vec3d,
+    depth: f64,
+    position: Vec3d,
+    hits: u32,
+    count: u32,
+    material_id: u32,
+    object_id: u32,
+}
+
+impl Default for AovPixel {
+    fn default() -> Self {
+        Self {
+            albedo: Vec3d::ZERO,
+            normal: Vec3d::ZERO,
+            depth: 0.0,
+            position: Vec3d::ZERO,
+            hits: 0,
+            count: 0,
+            material_id: 0,
+            object_id: 0,
+        }
+    }
+}
+
+impl AovPixel {
+    pub fn add(&mut self, sample: &AovSample) {
+        if self.count == 0 {
+            self.material_id = sample.material_id;
+            self.object_id = sample.object_id;
+        }
+        self.count += 1;
+        self.albedo += sample.albedo;
+
+        // geometric data only exists where something was hit
+        if sample.depth.is_finite() {
+            self.hits += 1;
+            self.normal += sample.normal;
+            self.depth += sample.depth;
+            self.position += sample.position;
+        }
+    }
+
+    pub fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        let [ar, ag, ab] = self.albedo.to_array();
+        let [nx, ny, nz] = self.normal.to_array();
+        let [px, py, pz] = self.position.to_array();
+        checkpoint::write_f64s(w, &[ar, ag, ab, nx, ny, nz, self.depth, px, py, pz])?;
+        [self.hits, self.count, self.material_id, self.object_id]
+            .into_iter()
+            .try_for_each(|value| checkpoint::write_u32(w, value))
+    }
+
+    pub fn read_from(r: &mut impl Read) -> io::Result<Self> {
+        let [ar, ag, ab, nx, ny, nz, depth, px, py, pz] = checkpoint::read_f64s(r)?;
+        Ok(Self {
+            albedo: Vec3d::new(ar, ag, ab),
+            normal: Vec3d::new(nx, ny, nz),
+            depth,
+            position: Vec3d::new(px, py, pz),
+            hits: checkpoint::read_u32(r)?,
+            count: checkpoint::read_u32(r)?,
+            material_id: checkpoint::read_u32(r)?,
+            object_id: checkpoint::read_u32(r)?,
+        })
+    }
+
+    pub fn albedo(&self) -> Color {
+        self.albedo / self.count.max(1) as f64
+    }
+
+    /// averaged normal, renormalized; zero for background pixels
+    pub fn normal(&self) -> Vec3d {
+        if self.normal.near_zero() {
+            return Vec3d::ZERO;
+        }
+        self.normal.unit_vec()
+    }
+
+    /// average depth of the samples that hit something, infinite for
+    /// background pixels
+    pub fn depth(&self) -> f64 {
+        if self.hits == 0 {
+            return f64::INFINITY;
+        }
+        self.depth / self.hits as f64
+    }
+
+    pub fn position(&self) -> Vec3d {
+        self.position / self.hits.max(1) as f64
+    }
+
+    pub fn material_id(&self) -> u32 {
+        self.material_id
+    }
+
+    pub fn object_id(&self) -> u32 {
+        self.object_id
+    }
+}
+
+/// Build the image layer of one AOV from the pixels of a film.
+pub fn layer(kind: AovKind, pixels: &[AovPixel]) -> ExrLayer {
+    let vector = |names: [&str; 3], get: fn(&AovPixel) -> Vec3d| {
+        names
+            .iter()
+            .enumerate()
+            .map(|(c, name)| {
+                let samples = pixels.iter().map(|p| get(p)[c] as f32).collect();
+                (name.to_string(), ExrSamples::Float(samples))
+            })
+            .collect()
+    };
+    let ids = |get: fn(&AovPixel) -> u32| {
+        vec![(
+            "id".to_owned(),
+            ExrSamples::Uint(pixels.iter().map(get).collect()),
+        )]
+    };
+
+    let channels = match kind {
+        AovKind::Albedo => vector(["R", "G", "B"], AovPixel::albedo),
+        AovKind::Normal => vector(["X", "Y", "Z"], AovPixel::normal),
+        AovKind::Position => vector(["X", "Y", "Z"], AovPixel::position),
+        AovKind::Depth => vec![(
+            "Z".to_owned(),
+            ExrSamples::Float(pixels.iter().map(|p| p.depth() as f32).collect()),
+        )],
+        AovKind::MaterialId => ids(AovPixel::material_id),
+        AovKind::ObjectId => ids(AovPixel::object_id),
+    };
+
+    ExrLayer {
+        name: Some(kind.name().to_owned()),
+        channels,
+    }
+}
@@ -1,15 +1,140 @@
-use std::{f64::INFINITY, fs, io::Write};
+use std::{
+    ops::Range,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
-use rayon::iter::ParallelIterator;
+use rayon::prelude::*;
 
 use crate::{
-    color::{self, Color},
+    aov::{AovKind, AovPixel, AovSample},
+    cancel::{CancelToken, Stop},
+    checkpoint::{self, CheckpointInfo},
+    color::Color,
+    denoise::DenoiseOptions,
+    error::{Error, Result},
+    film::{Film, PixelStats},
+    framebuffer::FrameBuffer,
     hittable::{HitRecord, Hittable},
-    ray::Ray,
+    progress::{self, Progress, ProgressCallback, ProgressEvent},
+    ray::{LANES, Ray, RayPacket},
+    sampler::{self, Sampler, SamplerKind},
+    spectrum,
+    tile::{self, Tile, TileOrder},
     util,
-    vec3::{self, Point3, SliceOp, Vec3},
+    vec3::{self, Float, Point3, Vec3},
 };
 
+/// Settings of a single render that are not part of the camera model.
+#[derive(Clone, Debug)]
+pub struct RenderOptions {
+    /// base seed of all per-pixel random streams, equal seeds give
+    /// bit-identical images whatever the thread count
+    pub seed: u64,
+    /// how pixel, lens and scattering dimensions are sampled
+    pub sampler: SamplerKind,
+    /// stop sampling pixels early once they are converged
+    pub adaptive: Option<AdaptiveOptions>,
+    /// render in passes of this many samples per pixel, writing the
+    /// outputs after every pass
+    pub progressive: Option<u32>,
+    /// edge length of the square tiles the image is split into
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    /// first-hit data to capture next to the image
+    pub aovs: Vec<AovKind>,
+    /// filter the image using the AOVs before it is written
+    pub denoise: Option<DenoiseOptions>,
+    /// save the render state periodically and at the end
+    pub checkpoint: Option<CheckpointOptions>,
+    /// checkpoint to continue from, the camera's samples per pixel become
+    /// the new target. Stratified renders have to keep their target
+    pub resume: Option<String>,
+    /// stop as soon as this is cancelled, keeping the samples taken so far.
+    /// Pixels the render didn't get to have no samples and stay black,
+    /// `ProgressEvent::Finished` counts them
+    pub cancel: Option<CancelToken>,
+    /// stop after this much time, the camera's samples per pixel become an
+    /// upper limit. The render runs in passes so that all pixels progress
+    /// evenly, one sample each unless `progressive` says otherwise. Every
+    /// pixel gets its first sample even if that takes longer
+    pub time_budget: Option<Duration>,
+    /// trace camera rays in packets of `ray::LANES`, the image is the same
+    /// either way
+    pub ray_packets: bool,
+    /// receives what the render is doing, the default is to stay silent
+    pub progress: Option<ProgressCallback>,
+    /// trace a single wavelength per path instead of RGB, for dispersion.
+    /// Colors are upsampled to spectra, which adds color noise
+    pub spectral: bool,
+}
+
+impl RenderOptions {
+    /// Whether the film needs AOVs, the denoiser uses them even when they
+    /// aren't written.
+    pub fn captures_aovs(&self) -> bool {
+        !self.aovs.is_empty() || self.denoise.is_some()
+    }
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            sampler: SamplerKind::default(),
+            adaptive: None,
+            progressive: None,
+            tile_size: 32,
+            tile_order: TileOrder::default(),
+            aovs: Vec::new(),
+            denoise: None,
+            checkpoint: None,
+            resume: None,
+            cancel: None,
+            time_budget: None,
+            ray_packets: true,
+            progress: None,
+            spectral: false,
+        }
+    }
+}
+
+/// Closest hit accepted along a ray. Scattered rays don't need a margin,
+/// `HitRecord::spawn` already moves them off the surface they leave from.
+const T_MIN: Float = 0.0;
+
+#[derive(Clone, Debug)]
+pub struct CheckpointOptions {
+    pub path: String,
+    /// minimum time between two checkpoints
+    pub interval: Duration,
+}
+
+/// Per-pixel adaptive sampling, the camera's samples per pixel become the
+/// upper limit.
+#[derive(Clone, Copy, Debug)]
+pub struct AdaptiveOptions {
+    /// samples every pixel gets before its noise estimate is trusted
+    pub min_samples: u32,
+    /// a pixel is done once its estimated display noise drops below this
+    pub threshold: f64,
+}
+
+impl AdaptiveOptions {
+    pub fn converged(&self, stats: &PixelStats) -> bool {
+        stats.count() >= self.min_samples && stats.error() <= self.threshold
+    }
+}
+
+impl Default for AdaptiveOptions {
+    fn default() -> Self {
+        Self {
+            min_samples: 16,
+            threshold: 0.01,
+        }
+    }
+}
+
 pub struct Camera {
     image_width: i32,
     image_height: i32,
@@ -17,166 +142,559 @@ pub struct Camera {
     pixel00_loc: Point3,
     pixel_delta_u: Vec3,
     pixel_delta_v: Vec3,
-    pixel_sample_scale: f64,
+    /// unit vector pointing backwards from the view direction
+    w: Vec3,
     sample_per_pixel: i32,
     max_depth: i32,
-    defocus_angle: f64,
+    defocus_angle: Float,
     defocus_disk_u: Vec3,
     defocus_disk_v: Vec3,
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         aspect_ratio: f64,
         image_width: i32,
         sample_per_pixel: i32,
         max_depth: i32,
-        vfov: f64,
+        vfov: Float,
         look_from: Vec3,
         look_at: Vec3,
         vup: Vec3,
-        defocus_angle: f64,
-        focus_dist: f64,
-    ) -> Self {
+        defocus_angle: Float,
+        focus_dist: Float,
+    ) -> Result<Self> {
+        if image_width <= 0 {
+            return Err(Error::InvalidCamera("image width must be positive"));
+        }
+        if !(aspect_ratio > 0.0 && aspect_ratio.is_finite()) {
+            return Err(Error::InvalidCamera("aspect ratio must be positive"));
+        }
+        if sample_per_pixel <= 0 {
+            return Err(Error::InvalidCamera("samples per pixel must be positive"));
+        }
+        if max_depth < 0 {
+            return Err(Error::InvalidCamera("max depth must not be negative"));
+        }
+        if !(vfov > 0.0 && vfov < 180.0) {
+            return Err(Error::InvalidCamera(
+                "vfov must be between 0 and 180 degrees",
+            ));
+        }
+        if !(focus_dist > 0.0 && focus_dist.is_finite()) {
+            return Err(Error::InvalidCamera("focus distance must be positive"));
+        }
+        if look_from == look_at {
+            return Err(Error::InvalidCamera(
+                "look_from and look_at are the same point",
+            ));
+        }
+        if vup.cross(look_from - look_at).near_zero() {
+            return Err(Error::InvalidCamera(
+                "vup is parallel to the view direction",
+            ));
+        }
+
         // calculate image height, it should be at least 1
         let image_height = (image_width as f64 / aspect_ratio) as i32;
         let image_height = image_height.max(1);
 
-        let pixel_sample_scale = 1.0 / sample_per_pixel as f64;
-
         // camera
         let center = look_from;
         let theta = util::degrees_to_radians(vfov);
         let h = (theta / 2.0).tan();
         let viewport_height = 2.0 * h * focus_dist;
-        let viewport_width = viewport_height * (image_width as f64 / image_height as f64);
+        let viewport_width = viewport_height * (image_width as Float / image_height as Float);
 
         // calculate the u,v,w unit basic vectors for the camera coordinate frame.
-        let w = look_from.sub(look_at).unit_vec();
+        let w = (look_from - look_at).unit_vec();
         let u = vup.cross(w).unit_vec();
         let v = w.cross(u);
 
         // calculate the vectors across the horizontal and down the vertical viewport edges
-        let viewport_u = u.mul_f(viewport_width);
-        let viewport_v = v.neg().mul_f(viewport_height);
+        let viewport_u = u * viewport_width;
+        let viewport_v = -v * viewport_height;
 
         // calculate the horizontal and vertical delta vectors from pixel to pixel
-        let pixel_delta_u = viewport_u.div_f(image_width as f64);
-        let pixel_delta_v = viewport_v.div_f(image_height as f64);
+        let pixel_delta_u = viewport_u / image_width as Float;
+        let pixel_delta_v = viewport_v / image_height as Float;
 
         // calculate the location of the upper left pixel
-        let viewport_upper_left = center
-            .sub(w.mul_f(focus_dist))
-            .sub(viewport_u.div_f(2.0))
-            .sub(viewport_v.div_f(2.0));
-        let pixel00_loc = viewport_upper_left.add(pixel_delta_u.add(pixel_delta_v).mul_f(0.5));
+        let viewport_upper_left = center - w * focus_dist - viewport_u / 2.0 - viewport_v / 2.0;
+        let pixel00_loc = viewport_upper_left + (pixel_delta_u + pixel_delta_v) * 0.5;
 
         // calculate the camera defocus disk basis vectors
         let defocus_radis = focus_dist * util::degrees_to_radians(defocus_angle / 2.0).tan();
-        let defocus_disk_u = u.mul_f(defocus_radis);
-        let defocus_disk_v = v.mul_f(defocus_radis);
+        let defocus_disk_u = u * defocus_radis;
+        let defocus_disk_v = v * defocus_radis;
 
-        Self {
+        Ok(Self {
             image_width,
             image_height,
             center,
             pixel00_loc,
             pixel_delta_u,
             pixel_delta_v,
-            pixel_sample_scale,
+            w,
             sample_per_pixel,
             max_depth,
             defocus_angle,
             defocus_disk_u,
             defocus_disk_v,
-        }
+        })
     }
 
-    pub fn render(&self, world: &dyn Hittable) {
-        println!("Starting render...\n");
+    /// Width and height of the image in pixels.
+    pub fn image_size(&self) -> (usize, usize) {
+        (self.image_width as usize, self.image_height as usize)
+    }
+
+    pub fn samples_per_pixel(&self) -> u32 {
+        self.sample_per_pixel as u32
+    }
+
+    /// Render `world` and return the final, possibly denoised, image.
+    pub fn render(&self, world: &dyn Hittable, options: &RenderOptions) -> Result<FrameBuffer> {
+        let film = self.render_film(world, options, |_| Ok(()))?;
+        Ok(film.image(options.denoise.as_ref()))
+    }
+
+    /// Render `world` into a film, which keeps the AOVs and sample counts
+    /// as well. With progressive rendering `on_pass` sees the film after
+    /// every pass but the last, an error from it ends the render.
+    pub fn render_film(
+        &self,
+        world: &dyn Hittable,
+        options: &RenderOptions,
+        mut on_pass: impl FnMut(&Film) -> Result<()>,
+    ) -> Result<Film> {
+        let start_time = Instant::now();
+        let callback = options.progress.as_ref();
+
+        let width = self.image_width as usize;
+        let height = self.image_height as usize;
+        progress::report(callback, || ProgressEvent::Started {
+            width,
+            height,
+            resumed: options.resume.clone(),
+        });
+        let with_aovs = options.captures_aovs();
+        let checkpoint_info = CheckpointInfo {
+            seed: options.seed,
+            sampler: options.sampler,
+            spectral: options.spectral,
+            samples_per_pixel: self.samples_per_pixel(),
+        };
+
+        let film = match &options.resume {
+            Some(path) => {
+                let (film, info) = checkpoint::load(path)?;
+                if film.width() != width || film.height() != height {
+                    return Err(Error::Checkpoint(format!(
+                        "checkpoint is {}x{}, the camera renders {width}x{height}",
+                        film.width(),
+                        film.height()
+                    )));
+                }
+                if let Some(mismatch) = checkpoint_info.resume_mismatch(&info) {
+                    return Err(Error::Checkpoint(mismatch));
+                }
+                if with_aovs && film.aovs().is_empty() {
+                    return Err(Error::Checkpoint(
+                        "checkpoint has no AOVs to continue".to_owned(),
+                    ));
+                }
+                film
+            }
+            None => Film::new(width, height, with_aovs),
+        };
 
-        // image file
-        let mut ppm_file =
-            fs::File::create("image.ppm").expect("Failed to create/truncate ppm image file");
+        // a regular render is one pass with every sample
+        let spp = self.sample_per_pixel as u32;
+        let pass_samples = match (options.progressive, options.time_budget) {
+            (Some(samples), _) => samples,
+            (None, Some(_)) => 1,
+            (None, None) => spp,
+        }
+        .max(1);
+        let passes = spp.div_ceil(pass_samples);
 
-        // write ppm header to image file
-        ppm_file
-            .write_fmt(format_args!(
-                "P3\n{} {}\n255\n",
-                self.image_width, self.image_height
-            ))
-            .expect("Failed to write header");
+        let tiles = tile::tiles(width, height, options.tile_size, options.tile_order);
+        let progress = Progress::new(tiles.len() * passes as usize, callback);
 
-        // prepare pixel count and buffers
-        let pixel_count = self.image_height as usize * self.image_width as usize;
-        println!(
-            "Resolution: {} x {} = {}",
-            self.image_width, self.image_height, pixel_count
+        // finished tiles are merged right away, so a checkpoint can be taken
+        // between any two of them
+        let film = Mutex::new(film);
+        let last_checkpoint = Mutex::new(Instant::now());
+        let stop = Stop::new(
+            options.cancel.clone(),
+            options.time_budget.map(|budget| start_time + budget),
         );
+        let mut stopped = false;
+
+        for pass in 0..passes {
+            let pass_end = ((pass + 1) * pass_samples).min(spp);
+
+            // tiles are handed out in order, each one works on its own copy
+            // of the pixels and only locks the film to read and merge them
+            tiles.iter().par_bridge().for_each(|tile| {
+                let mut pixels: Vec<_> = {
+                    let film = film.lock().unwrap();
+                    tile.pixels()
+                        .map(|(i, j)| {
+                            let idx = j * width + i;
+                            (film.pixels()[idx], film.aovs().get(idx).copied())
+                        })
+                        .collect()
+                };
+                let fewest_samples = pixels.iter().map(|(stats, _)| stats.count()).min();
+                if stop.reached_at(fewest_samples.unwrap_or(0)) {
+                    return;
+                }
+
+                let rays = self.render_tile(world, options, tile, &mut pixels, pass_end, &stop);
+
+                let mut film = film.lock().unwrap();
+                for ((i, j), (stats, aov)) in tile.pixels().zip(pixels) {
+                    film.pixels_mut()[j * width + i] = stats;
+                    if let Some(aov) = aov {
+                        film.aovs_mut()[j * width + i] = aov;
+                    }
+                }
 
-        println!("Rendering ...");
-        let start_time = std::time::Instant::now();
-
-        let mut img_buf =
-            image::ImageBuffer::new(self.image_width as u32, self.image_height as u32);
-
-        // SAFETY: Since, each pixel is computed independently,
-        // it is safe to access individual pixel in parallel
-        img_buf
-            .par_enumerate_pixels_mut()
-            .for_each(|(i, j, pixel)| {
-                let mut pixel_color = vec3::init();
-                for _sample in 0..self.sample_per_pixel {
-                    let r = self.get_ray(i as f64, j as f64);
-                    let color = Self::ray_color(r, self.max_depth, world);
-                    pixel_color.add_assign(color);
+                // copy the film for a due checkpoint and write the copy once
+                // the other tiles can merge again. A thread already writing
+                // one holds `last_checkpoint`, the others don't wait for it
+                let due = options.checkpoint.as_ref().and_then(|checkpoint| {
+                    let last_checkpoint = last_checkpoint.try_lock().ok()?;
+                    (last_checkpoint.elapsed() >= checkpoint.interval)
+                        .then(|| (checkpoint, last_checkpoint, film.clone()))
+                });
+                drop(film);
+
+                if let Some((checkpoint, mut last_checkpoint, snapshot)) = due {
+                    // not worth losing the render over, the final
+                    // checkpoint reports its error
+                    if let Err(e) = checkpoint::save(&checkpoint.path, &snapshot, &checkpoint_info)
+                    {
+                        progress::report(callback, || ProgressEvent::CheckpointFailed {
+                            path: checkpoint.path.clone(),
+                            error: e.to_string(),
+                        });
+                    }
+                    *last_checkpoint = Instant::now();
                 }
 
-                *pixel = image::Rgb(color::get_pixel(pixel_color.mul_f(self.pixel_sample_scale)));
+                progress.tile_done(rays);
             });
-        println!("elapsed: {:?}", start_time.elapsed());
 
-        println!("Writing pixels to file");
-        img_buf.save("image.png").unwrap();
-        println!("Done");
+            // pixels keep whatever samples they got, the image is averaged
+            // per pixel so it stays correctly exposed
+            if stop.reached() {
+                stopped = true;
+                break;
+            }
+
+            if options.progressive.is_some() {
+                progress::report(callback, || ProgressEvent::PassDone {
+                    pass: pass + 1,
+                    passes,
+                    samples_per_pixel: pass_end,
+                    elapsed: start_time.elapsed(),
+                });
+                if pass + 1 < passes {
+                    on_pass(&film.lock().unwrap())?;
+                }
+            }
+        }
+        let film = film.into_inner().unwrap();
+
+        // the final state, to add more samples later on
+        if let Some(checkpoint) = &options.checkpoint {
+            progress::report(callback, || ProgressEvent::WritingCheckpoint {
+                path: checkpoint.path.clone(),
+            });
+            checkpoint::save(&checkpoint.path, &film, &checkpoint_info)?;
+        }
+
+        progress::report(callback, || ProgressEvent::Finished {
+            elapsed: start_time.elapsed(),
+            rays: progress.rays(),
+            stopped,
+            average_samples: film.average_samples(),
+            unsampled_pixels: film.pixels().iter().filter(|p| p.count() == 0).count(),
+        });
+
+        Ok(film)
     }
 
-    fn get_ray(&self, i: f64, j: f64) -> Ray {
-        let (x, y) = (util::random_float() - 0.5, util::random_float() - 0.5);
-        let pixel_sample = self
-            .pixel00_loc
-            .add(self.pixel_delta_u.mul_f(i + x))
-            .add(self.pixel_delta_v.mul_f(j + y));
+    /// Bring every pixel of `tile` up to `pass_end` samples, or until `stop`
+    /// is reached. `pixels` holds their state in the order of
+    /// `Tile::pixels`. Returns the number of rays traced.
+    pub fn render_tile(
+        &self,
+        world: &dyn Hittable,
+        options: &RenderOptions,
+        tile: &Tile,
+        pixels: &mut [(PixelStats, Option<AovPixel>)],
+        pass_end: u32,
+        stop: &Stop,
+    ) -> u64 {
+        // every pixel continues from its own sample count, which differs
+        // after adaptive sampling or resuming a checkpoint
+        let mut rays = 0;
+        if options.ray_packets && self.max_depth > 0 {
+            let coords: Vec<_> = tile.pixels().collect();
+            for (coords, pixels) in coords.chunks(LANES).zip(pixels.chunks_mut(LANES)) {
+                rays += self.sample_packet(world, options, coords, pixels, pass_end, stop);
+            }
+        } else {
+            for ((i, j), (stats, aov)) in tile.pixels().zip(pixels.iter_mut()) {
+                let samples = stats.count()..pass_end;
+                rays +=
+                    self.sample_pixel(world, options, (i, j), stats, aov.as_mut(), samples, stop);
+            }
+        }
+        rays
+    }
+
+    fn sampler(&self, options: &RenderOptions) -> Sampler {
+        Sampler {
+            kind: options.sampler,
+            seed: options.seed,
+            samples_per_pixel: self.sample_per_pixel as u64,
+        }
+    }
+
+    /// Trace the samples in `samples` for pixel `(i, j)`, or fewer once the
+    /// pixel is converged or `stop` is reached. Returns the number of rays
+    /// traced.
+    #[allow(clippy::too_many_arguments)]
+    fn sample_pixel(
+        &self,
+        world: &dyn Hittable,
+        options: &RenderOptions,
+        (i, j): (usize, usize),
+        stats: &mut PixelStats,
+        mut aov: Option<&mut AovPixel>,
+        samples: Range<u32>,
+        stop: &Stop,
+    ) -> u64 {
+        let sampler = self.sampler(options);
+        let idx = (j * self.image_width as usize + i) as u64;
+        let mut rays = 0;
+        for sample in samples {
+            if let Some(adaptive) = &options.adaptive
+                && adaptive.converged(stats)
+            {
+                break;
+            }
+            if stop.reached_at(stats.count()) {
+                break;
+            }
+
+            sampler.start(idx, sample as u64);
+            let r = self.get_ray(i as Float, j as Float);
+            let wavelength = Self::sample_wavelength(options);
+            let r = r.with_wavelength(wavelength.map(|(lambda, _)| lambda));
+            self.add_sample(stats, aov.as_deref_mut(), wavelength, |first_hit| {
+                Self::ray_color(r, self.max_depth, world, &mut rays, first_hit)
+            });
+        }
+        rays
+    }
+
+    /// `sample_pixel` for up to `LANES` pixels at once, whose camera rays
+    /// are traced as a packet. Every pixel takes its samples in the same
+    /// order as with `sample_pixel`, so the result is the same.
+    fn sample_packet(
+        &self,
+        world: &dyn Hittable,
+        options: &RenderOptions,
+        coords: &[(usize, usize)],
+        pixels: &mut [(PixelStats, Option<AovPixel>)],
+        pass_end: u32,
+        stop: &Stop,
+    ) -> u64 {
+        let sampler = self.sampler(options);
+        let first_sample = pixels.iter().map(|(stats, _)| stats.count()).min();
+        let mut rays = 0;
+        for sample in first_sample.unwrap_or(pass_end)..pass_end {
+            if stop.reached_at(sample) {
+                break;
+            }
+
+            // generate the camera ray of every pixel that is due for this
+            // sample, and remember where its sample continues
+            let mut cursors: [Option<sampler::SampleCursor>; LANES] = Default::default();
+            let mut camera_rays = [Ray::init(); LANES];
+            let mut wavelengths = [None; LANES];
+            for (lane, (&(i, j), (stats, _))) in coords.iter().zip(pixels.iter()).enumerate() {
+                let converged = options.adaptive.is_some_and(|a| a.converged(stats));
+                if stats.count() != sample || converged {
+                    continue;
+                }
+                let idx = (j * self.image_width as usize + i) as u64;
+                sampler.start(idx, sample as u64);
+                let r = self.get_ray(i as Float, j as Float);
+                let wavelength = Self::sample_wavelength(options);
+                camera_rays[lane] = r.with_wavelength(wavelength.map(|(lambda, _)| lambda));
+                wavelengths[lane] = wavelength;
+                cursors[lane] = Some(sampler::save());
+            }
+            let Some(active) = cursors.iter().position(Option::is_some) else {
+                continue;
+            };
+
+            // idle lanes repeat an active ray but accept no hit
+            let mut t_max = [Float::INFINITY; LANES];
+            for lane in 0..LANES {
+                if cursors[lane].is_none() {
+                    camera_rays[lane] = camera_rays[active];
+                    t_max[lane] = T_MIN;
+                }
+            }
+            let packet = RayPacket::new(camera_rays);
+            let hits = world.hit_packet(&packet, T_MIN, t_max);
+
+            for (lane, (stats, aov)) in pixels.iter_mut().enumerate() {
+                let Some(cursor) = &cursors[lane] else {
+                    continue;
+                };
+                sampler::restore(cursor);
+                self.add_sample(stats, aov.as_mut(), wavelengths[lane], |first_hit| {
+                    rays += 1;
+                    let r = camera_rays[lane];
+                    Self::shade(r, hits[lane], self.max_depth, world, &mut rays, first_hit)
+                });
+            }
+        }
+        rays
+    }
+
+    /// Wavelength and its density for the next spectral sample, drawn
+    /// right after the camera ray.
+    fn sample_wavelength(options: &RenderOptions) -> Option<(f64, f64)> {
+        options
+            .spectral
+            .then(|| spectrum::sample_wavelength(util::random_float()))
+    }
+
+    /// Add the color `trace` returns to the pixel, `trace` fills in the
+    /// first hit when the AOVs are captured. For a spectral sample `trace`
+    /// returns the radiance at `wavelength` in every channel.
+    fn add_sample(
+        &self,
+        stats: &mut PixelStats,
+        aov: Option<&mut AovPixel>,
+        wavelength: Option<(f64, f64)>,
+        trace: impl FnOnce(Option<&mut AovSample>) -> Color,
+    ) {
+        let trace = |first_hit| {
+            let color = trace(first_hit);
+            match wavelength {
+                Some((lambda, pdf)) => spectrum::to_rgb(color.x(), lambda, pdf),
+                None => color,
+            }
+        };
+        match aov {
+            Some(aov) => {
+                let mut first_hit = AovSample::background(Color::ZERO);
+                stats.add(trace(Some(&mut first_hit)));
+                if first_hit.object_id != 0 {
+                    let (center, w) = (self.center.to_f64(), self.w.to_f64());
+                    first_hit.depth = (first_hit.position - center).dot(-w);
+                }
+                aov.add(&first_hit);
+            }
+            None => stats.add(trace(None)),
+        }
+    }
+
+    fn get_ray(&self, i: Float, j: Float) -> Ray {
+        let (x, y) = util::random_2d();
+        let (x, y) = (x as Float - 0.5, y as Float - 0.5);
+        let pixel_sample =
+            self.pixel00_loc + self.pixel_delta_u * (i + x) + self.pixel_delta_v * (j + y);
 
         let ray_origin = if self.defocus_angle <= 0.0 {
             self.center
         } else {
-            let [x, y, _] = vec3::random_in_unit_disk();
-            self.center
-                .add(self.defocus_disk_u.mul_f(x))
-                .add(self.defocus_disk_v.mul_f(y))
+            let p = vec3::random_in_unit_disk();
+            self.center + self.defocus_disk_u * p.x() + self.defocus_disk_v * p.y()
         };
-        let ray_direction = pixel_sample.sub(ray_origin);
+        let ray_direction = pixel_sample - ray_origin;
         Ray::new(ray_origin, ray_direction)
     }
 
-    fn ray_color(r: Ray, depth: i32, world: &dyn Hittable) -> Color {
+    /// Radiance arriving along `r`, `first_hit` receives what the ray hits
+    /// first for the AOVs.
+    fn ray_color(
+        r: Ray,
+        depth: i32,
+        world: &dyn Hittable,
+        rays: &mut u64,
+        first_hit: Option<&mut AovSample>,
+    ) -> Color {
         if depth <= 0 {
-            return vec3::init();
+            return Color::ZERO;
         }
+        *rays += 1;
+
+        let hit = world.hit(&r, T_MIN..Float::INFINITY);
+        Self::shade(r, hit, depth, world, rays, first_hit)
+    }
+
+    /// The rest of `ray_color` once `r` has been traced, `hit` is what it
+    /// hit.
+    fn shade(
+        r: Ray,
+        hit: Option<HitRecord>,
+        depth: i32,
+        world: &dyn Hittable,
+        rays: &mut u64,
+        first_hit: Option<&mut AovSample>,
+    ) -> Color {
+        if let Some(record) = &hit {
+            let mat = record.material;
+            if let Some(aov) = first_hit {
+                *aov = AovSample {
+                    albedo: mat.albedo(),
+                    normal: record.normal.to_f64(),
+                    depth: 0.0,
+                    position: record.p.to_f64(),
+                    material_id: mat.id(),
+                    object_id: record.object_id as u32 + 1,
+                };
+            }
 
-        let mut record = HitRecord::init();
-        if let Some(mat) = world.hit(&r, 0.001..INFINITY, &mut record) {
+            let emitted = at_wavelength(mat.emitted(record), r.wavelength());
             let mut scattered = Ray::init();
-            let mut attenuation = vec3::init();
-            if mat.scatter(&r, &record, &mut attenuation, &mut scattered) {
-                return attenuation.mul(Self::ray_color(scattered, depth - 1, world));
+            let mut attenuation = Color::ZERO;
+            if mat.scatter(&r, record, &mut attenuation, &mut scattered) {
+                let attenuation = at_wavelength(attenuation, r.wavelength());
+                let scattered = record.spawn(scattered.with_wavelength(r.wavelength()));
+                return emitted
+                    + attenuation * Self::ray_color(scattered, depth - 1, world, rays, None);
             }
-            return vec3::init();
+            return emitted;
         }
 
-        let [_, y, _] = r.direction().unit_vec();
-        let a = 0.5 * (y + 1.0);
-        [1.0, 1.0, 1.0].mul_f(1.0 - a).add([0.5, 0.7, 1.0].mul_f(a))
+        let a = 0.5 * (r.direction().unit_vec().to_f64().y() + 1.0);
+        let sky = Color::ONE * (1.0 - a) + Color::new(0.5, 0.7, 1.0) * a;
+        if let Some(aov) = first_hit {
+            *aov = AovSample::background(sky);
+        }
+        at_wavelength(sky, r.wavelength())
+    }
+}
+
+/// `color` as seen by a ray of `wavelength`, the value of its spectrum in
+/// every channel. Without a wavelength the color stays as it is.
+fn at_wavelength(color: Color, wavelength: Option<f64>) -> Color {
+    match wavelength {
+        Some(lambda) => Color::splat(spectrum::rgb_to_spectrum(color, lambda)),
+        None => color,
     }
 }
@@ -0,0 +1,57 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Instant,
+};
+
+/// Shared flag to stop a running render from another thread.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// When a render has to end before all of its samples are taken, the
+/// default never stops.
+#[derive(Clone, Debug, Default)]
+pub struct Stop {
+    cancel: Option<CancelToken>,
+    deadline: Option<Instant>,
+}
+
+impl Stop {
+    pub fn new(cancel: Option<CancelToken>, deadline: Option<Instant>) -> Self {
+        Self { cancel, deadline }
+    }
+
+    pub fn reached(&self) -> bool {
+        self.cancel.as_ref().is_some_and(CancelToken::is_cancelled)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Whether a pixel that has taken `samples` so far has to stop. Only
+    /// cancelling leaves pixels without a sample, the deadline waits for
+    /// the first one.
+    pub fn reached_at(&self, samples: u32) -> bool {
+        if samples == 0 {
+            self.cancel.as_ref().is_some_and(CancelToken::is_cancelled)
+        } else {
+            self.reached()
+        }
+    }
+}
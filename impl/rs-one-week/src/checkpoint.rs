@@ -0,0 +1,186 @@
+use std::{
+    fs,
+    io::{self, BufReader, BufWriter, Read, Write},
+};
+
+use crate::{
+    aov::AovPixel,
+    film::{Film, PixelStats},
+    sampler::SamplerKind,
+};
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 3;
+
+/// Render state stored next to the accumulated pixels.
+///
+/// Every sample draws from a random stream derived from the seed, the pixel
+/// and the sample number, so the seed and the per-pixel sample counts kept
+/// in the film are all the RNG state there is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CheckpointInfo {
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    /// spectral samples don't mix with RGB ones of the same seed
+    pub spectral: bool,
+    /// the target of the render that was saved, resuming may change it
+    /// unless the sampler lays its samples out for it
+    pub samples_per_pixel: u32,
+}
+
+impl CheckpointInfo {
+    /// Why a render with `self` can't continue the checkpoint `saved`,
+    /// if it can't.
+    pub fn resume_mismatch(&self, saved: &CheckpointInfo) -> Option<String> {
+        if (self.seed, self.sampler, self.spectral) != (saved.seed, saved.sampler, saved.spectral) {
+            return Some(format!(
+                "checkpoint was rendered with {saved:?}, not {self:?}"
+            ));
+        }
+        // the strata cover the target sample count, samples past the old
+        // target would only fill part of a new grid
+        if self.sampler == SamplerKind::Stratified
+            && self.samples_per_pixel != saved.samples_per_pixel
+        {
+            return Some(format!(
+                "stratified checkpoint was rendered for {} samples per pixel, not {}",
+                saved.samples_per_pixel, self.samples_per_pixel
+            ));
+        }
+        None
+    }
+}
+
+/// Write the film to `path`.
+///
+/// The data goes to a temporary file first that then replaces `path`, so a
+/// crash while writing leaves the previous checkpoint intact.
+pub fn save(path: &str, film: &Film, info: &CheckpointInfo) -> io::Result<()> {
+    let tmp_path = format!("{path}.tmp");
+    let mut w = BufWriter::new(fs::File::create(&tmp_path)?);
+
+    w.write_all(MAGIC)?;
+    write_u32(&mut w, VERSION)?;
+    write_u32(&mut w, film.width() as u32)?;
+    write_u32(&mut w, film.height() as u32)?;
+    write_u64(&mut w, info.seed)?;
+    write_u32(&mut w, info.samples_per_pixel)?;
+    w.write_all(&[
+        sampler_to_u8(info.sampler),
+        !film.aovs().is_empty() as u8,
+        info.spectral as u8,
+    ])?;
+
+    for stats in film.pixels() {
+        stats.write_to(&mut w)?;
+    }
+    for aov in film.aovs() {
+        aov.write_to(&mut w)?;
+    }
+
+    w.flush()?;
+    drop(w);
+    fs::rename(tmp_path, path)
+}
+
+/// Read a film written by [`save`].
+pub fn load(path: &str) -> io::Result<(Film, CheckpointInfo)> {
+    let mut r = BufReader::new(fs::File::open(path)?);
+
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a checkpoint file"));
+    }
+    let version = read_u32(&mut r)?;
+    if version != VERSION {
+        return Err(invalid(&format!(
+            "unsupported checkpoint version {version}"
+        )));
+    }
+
+    let width = read_u32(&mut r)? as usize;
+    let height = read_u32(&mut r)? as usize;
+    let seed = read_u64(&mut r)?;
+    let samples_per_pixel = read_u32(&mut r)?;
+    let mut flags = [0; 3];
+    r.read_exact(&mut flags)?;
+    let sampler = sampler_from_u8(flags[0]).ok_or_else(|| invalid("unknown sampler"))?;
+    let with_aovs = flags[1] != 0;
+    let spectral = flags[2] != 0;
+
+    let mut film = Film::new(width, height, with_aovs);
+    for stats in film.pixels_mut() {
+        *stats = PixelStats::read_from(&mut r)?;
+    }
+    for aov in film.aovs_mut() {
+        *aov = AovPixel::read_from(&mut r)?;
+    }
+
+    Ok((
+        film,
+        CheckpointInfo {
+            seed,
+            sampler,
+            spectral,
+            samples_per_pixel,
+        },
+    ))
+}
+
+pub(crate) fn sampler_to_u8(sampler: SamplerKind) -> u8 {
+    match sampler {
+        SamplerKind::Random => 0,
+        SamplerKind::Stratified => 1,
+        SamplerKind::Halton => 2,
+        SamplerKind::Sobol => 3,
+    }
+}
+
+pub(crate) fn sampler_from_u8(value: u8) -> Option<SamplerKind> {
+    match value {
+        0 => Some(SamplerKind::Random),
+        1 => Some(SamplerKind::Stratified),
+        2 => Some(SamplerKind::Halton),
+        3 => Some(SamplerKind::Sobol),
+        _ => None,
+    }
+}
+
+pub(crate) fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub(crate) fn write_u32(w: &mut impl Write, value: u32) -> io::Result<()> {
+    w.write_all(&value.to_le_bytes())
+}
+
+pub(crate) fn write_u64(w: &mut impl Write, value: u64) -> io::Result<()> {
+    w.write_all(&value.to_le_bytes())
+}
+
+pub(crate) fn write_f64s(w: &mut impl Write, values: &[f64]) -> io::Result<()> {
+    values
+        .iter()
+        .try_for_each(|value| w.write_all(&value.to_le_bytes()))
+}
+
+pub(crate) fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub(crate) fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+pub(crate) fn read_f64s<const N: usize>(r: &mut impl Read) -> io::Result<[f64; N]> {
+    let mut values = [0.0; N];
+    for value in values.iter_mut() {
+        *value = f64::from_bits(read_u64(r)?);
+    }
+    Ok(values)
+}
@@ -1,14 +1,19 @@
-use crate::vec3::Vec3;
+use crate::{tonemap::ToneMapping, vec3::Vec3d};
 
-pub type Color = Vec3;
+pub type Color = Vec3d;
 
-fn linear_to_gamma(linear: f64) -> f64 {
-    if linear > 0.0 { linear.sqrt() } else { 0.0 }
+/// sRGB transfer function (IEC 61966-2-1) for a linear value in `0..1`.
+pub fn linear_to_srgb(linear: f64) -> f64 {
+    if linear <= 0.0031308 {
+        12.92 * linear.max(0.0)
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
 }
 
-pub fn get_pixel(pixel: Color) -> [u8; 3] {
-    let [r, g, b] = pixel;
-    let [r, g, b] = [linear_to_gamma(r), linear_to_gamma(g), linear_to_gamma(b)];
+pub fn get_pixel(pixel: Color, tone_mapping: &ToneMapping) -> [u8; 3] {
+    let [r, g, b] = tone_mapping.apply(pixel).to_array();
+    let [r, g, b] = [linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b)];
 
     let range = 0.000..0.999;
     [
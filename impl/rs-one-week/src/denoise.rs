@@ -0,0 +1,155 @@
+use rayon::prelude::*;
+
+use crate::{aov::AovPixel, color::Color, framebuffer::FrameBuffer};
+
+/// Edge-avoiding à-trous wavelet filter settings, see Dammertz et al.,
+/// "Edge-Avoiding À-Trous Wavelet Transform for fast Global Illumination
+/// Filtering" (HPG 2010). A sigma of 0 leaves that guide out.
+#[derive(Clone, Copy, Debug)]
+pub struct DenoiseOptions {
+    /// filter passes, each doubles the footprint; 4 cover 61 pixels
+    pub iterations: u32,
+    /// tolerance for color differences, halved every iteration
+    pub sigma_color: f64,
+    /// tolerance for the distance between shading normals
+    pub sigma_normal: f64,
+    /// tolerance for depth differences, relative to the pixel's depth
+    pub sigma_depth: f64,
+    /// tolerance for albedo differences
+    pub sigma_albedo: f64,
+}
+
+impl Default for DenoiseOptions {
+    fn default() -> Self {
+        Self {
+            iterations: 4,
+            sigma_color: 0.2,
+            sigma_normal: 0.3,
+            sigma_depth: 0.05,
+            sigma_albedo: 0.2,
+        }
+    }
+}
+
+/// B3 spline, the filter kernel of every iteration
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Denoise `image` guided by the albedo, normal and depth of `aovs`, which
+/// needs one for every pixel.
+///
+/// The albedo is divided out first, so textures stay sharp and only the
+/// lighting gets blurred, then multiplied back in at the end.
+pub fn denoise(image: &FrameBuffer, aovs: &[AovPixel], options: &DenoiseOptions) -> FrameBuffer {
+    let (width, height) = (image.width(), image.height());
+    assert_eq!(
+        aovs.len(),
+        width * height,
+        "The denoiser needs the AOVs of every pixel"
+    );
+
+    let albedo: Vec<Color> = aovs.iter().map(AovPixel::albedo).collect();
+    let normal: Vec<Color> = aovs.iter().map(AovPixel::normal).collect();
+    let depth: Vec<f64> = aovs.iter().map(AovPixel::depth).collect();
+
+    let mut current: Vec<Color> = image
+        .pixels()
+        .iter()
+        .zip(albedo.iter())
+        .map(|(c, a)| demodulate(*c, *a))
+        .collect();
+    let mut next = vec![Color::ZERO; current.len()];
+
+    for iteration in 0..options.iterations {
+        let step = 1isize << iteration;
+        let sigma_color = options.sigma_color * 0.5f64.powi(iteration as i32);
+
+        next.par_chunks_mut(width).enumerate().for_each(|(y, row)| {
+            for (x, out) in row.iter_mut().enumerate() {
+                let p = y * width + x;
+                let cp = compress(current[p]);
+
+                let mut sum = Color::ZERO;
+                let mut weight_sum = 0.0;
+                for (ky, hy) in KERNEL.iter().enumerate() {
+                    let qy = y as isize + (ky as isize - 2) * step;
+                    if qy < 0 || qy >= height as isize {
+                        continue;
+                    }
+                    for (kx, hx) in KERNEL.iter().enumerate() {
+                        let qx = x as isize + (kx as isize - 2) * step;
+                        if qx < 0 || qx >= width as isize {
+                            continue;
+                        }
+                        let q = qy as usize * width + qx as usize;
+
+                        let dc = (cp - compress(current[q])).len_squared();
+                        let dn = (normal[p] - normal[q]).len_squared();
+                        let da = (albedo[p] - albedo[q]).len_squared();
+                        let dd = relative_depth(depth[p], depth[q]);
+
+                        let w = hx
+                            * hy
+                            * edge_weight(dc, sigma_color)
+                            * edge_weight(dn, options.sigma_normal)
+                            * edge_weight(da, options.sigma_albedo)
+                            * edge_weight(dd, options.sigma_depth);
+
+                        sum += current[q] * w;
+                        weight_sum += w;
+                    }
+                }
+
+                // the center tap always has weight, no division by zero
+                *out = sum / weight_sum;
+            }
+        });
+
+        std::mem::swap(&mut current, &mut next);
+    }
+
+    let mut out = FrameBuffer::new(width, height);
+    out.pixels_mut()
+        .iter_mut()
+        .zip(current.iter().zip(albedo.iter()))
+        .for_each(|(out, (c, a))| *out = *c * albedo_floor(*a));
+    out
+}
+
+/// Weight of a neighbor whose guide differs by the squared distance `d`,
+/// a `sigma` of 0 ignores the guide.
+fn edge_weight(d: f64, sigma: f64) -> f64 {
+    if sigma > 0.0 {
+        (-d / (sigma * sigma)).exp()
+    } else {
+        1.0
+    }
+}
+
+fn albedo_floor(albedo: Color) -> Color {
+    albedo.map(|c| c.max(0.01))
+}
+
+fn demodulate(color: Color, albedo: Color) -> Color {
+    let [r, g, b] = color.to_array();
+    let [ar, ag, ab] = albedo_floor(albedo).to_array();
+    Color::new(r / ar, g / ag, b / ab)
+}
+
+/// squeeze HDR values so a few very bright samples don't dominate the
+/// color distance
+fn compress(color: Color) -> Color {
+    color.map(|c| c / (1.0 + c))
+}
+
+fn relative_depth(p: f64, q: f64) -> f64 {
+    match (p.is_finite(), q.is_finite()) {
+        (true, true) => {
+            let d = (p - q) / p.max(1e-4);
+            d * d
+        }
+        // background next to background
+        (false, false) => 0.0,
+        // never blur across a silhouette against the sky
+        _ => f64::INFINITY,
+    }
+}
@@ -0,0 +1,472 @@
+//! Splitting a render over several processes.
+//!
+//! A coordinator listens on a TCP port and hands tiles to the workers that
+//! connect to it. Every worker loads the same scene file, renders the tiles
+//! it is given with all samples and sends the accumulated float pixel state
+//! back, which the coordinator merges into its film. Pixel samples only
+//! depend on the seed, pixel and sample number, so the merged image is the
+//! same as a local render.
+//!
+//! All numbers are little-endian:
+//!
+//! ```text
+//! worker -> coordinator  "RTDR" version:u32 scene_hash:u64
+//! coordinator -> worker  accepted:u8, then if accepted
+//!                        seed:u64 sampler:u8 aovs:u8 adaptive:u8 spectral:u8
+//!                        min_samples:u32 threshold:f64
+//! coordinator -> worker  1:u8 x0:u32 x1:u32 y0:u32 y1:u32, or 0:u8 when done
+//! worker -> coordinator  rays:u64 then the stats (and AOVs) of every pixel
+//! ```
+
+use std::{
+    collections::VecDeque,
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Condvar, Mutex, mpsc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    aov::{AovKind, AovPixel},
+    camera::{AdaptiveOptions, Camera, RenderOptions},
+    cancel::Stop,
+    checkpoint::{self, read_u32, read_u64, write_u32, write_u64},
+    error,
+    film::{Film, PixelStats},
+    hittable::Hittable,
+    progress::{self, Progress, ProgressEvent},
+    sampler::SamplerKind,
+    tile::{self, Tile},
+};
+
+const MAGIC: &[u8; 4] = b"RTDR";
+const VERSION: u32 = 2;
+
+/// How long a worker keeps trying to reach a coordinator that isn't up yet.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+type TilePixels = Vec<(PixelStats, Option<AovPixel>)>;
+
+/// Tiles waiting for a worker. Idle connections sleep until a tile comes
+/// back from a worker that dropped or the render is over.
+struct TileQueue {
+    /// the tiles left and whether the render is finished
+    state: Mutex<(VecDeque<Tile>, bool)>,
+    changed: Condvar,
+}
+
+impl TileQueue {
+    fn new(tiles: Vec<Tile>) -> Self {
+        Self {
+            state: Mutex::new((VecDeque::from(tiles), false)),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// The next tile to render, `None` once every tile is in.
+    fn pop(&self) -> Option<Tile> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let (tiles, finished) = &mut *state;
+            if let Some(tile) = tiles.pop_front() {
+                return Some(tile);
+            }
+            if *finished {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Hand `tile` to the next worker asking for one.
+    fn requeue(&self, tile: Tile) {
+        self.state.lock().unwrap().0.push_front(tile);
+        self.changed.notify_one();
+    }
+
+    fn finish(&self) {
+        self.state.lock().unwrap().1 = true;
+        self.changed.notify_all();
+    }
+
+    fn is_finished(&self) -> bool {
+        self.state.lock().unwrap().1
+    }
+}
+
+/// The render options that have to be the same on every worker.
+#[derive(Clone, Copy, Debug)]
+struct JobSettings {
+    seed: u64,
+    sampler: SamplerKind,
+    aovs: bool,
+    adaptive: Option<AdaptiveOptions>,
+    spectral: bool,
+}
+
+impl JobSettings {
+    fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        write_u64(w, self.seed)?;
+        let adaptive = self.adaptive.unwrap_or_default();
+        w.write_all(&[
+            checkpoint::sampler_to_u8(self.sampler),
+            self.aovs as u8,
+            self.adaptive.is_some() as u8,
+            self.spectral as u8,
+        ])?;
+        write_u32(w, adaptive.min_samples)?;
+        write_u64(w, adaptive.threshold.to_bits())
+    }
+
+    fn read_from(r: &mut impl Read) -> io::Result<Self> {
+        let seed = read_u64(r)?;
+        let mut flags = [0; 4];
+        r.read_exact(&mut flags)?;
+        let sampler = checkpoint::sampler_from_u8(flags[0])
+            .ok_or_else(|| checkpoint::invalid("unknown sampler"))?;
+        let adaptive = AdaptiveOptions {
+            min_samples: read_u32(r)?,
+            threshold: f64::from_bits(read_u64(r)?),
+        };
+
+        Ok(Self {
+            seed,
+            sampler,
+            aovs: flags[1] != 0,
+            adaptive: (flags[2] != 0).then_some(adaptive),
+            spectral: flags[3] != 0,
+        })
+    }
+}
+
+/// Hash identifying a scene file, workers must have loaded the same one.
+pub fn scene_hash(source: &str) -> u64 {
+    // FNV-1a
+    source.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Render the image on the workers connecting to `listen`, returns once
+/// every tile is back. Progressive passes, checkpoints, resuming and time
+/// budgets only apply to local renders, asking for them is an error.
+pub fn coordinate(
+    listen: &str,
+    camera: &Camera,
+    scene_hash: u64,
+    options: &RenderOptions,
+) -> error::Result<Film> {
+    let local_only = [
+        (
+            options.progressive.is_some(),
+            "distributed renders can't be progressive",
+        ),
+        (
+            options.checkpoint.is_some(),
+            "distributed renders can't write checkpoints",
+        ),
+        (
+            options.resume.is_some(),
+            "distributed renders can't resume a checkpoint",
+        ),
+        (
+            options.time_budget.is_some(),
+            "distributed renders can't have a time budget",
+        ),
+    ];
+    if let Some(&(_, message)) = local_only.iter().find(|(asked, _)| *asked) {
+        return Err(error::Error::InvalidOptions(message));
+    }
+    let listener = TcpListener::bind(listen)?;
+    let callback = options.progress.as_ref();
+    let addr = listener.local_addr()?;
+    progress::report(callback, || ProgressEvent::Listening {
+        addr: addr.to_string(),
+    });
+
+    let (width, height) = camera.image_size();
+    let tiles = tile::tiles(width, height, options.tile_size, options.tile_order);
+    let total_tiles = tiles.len();
+
+    let settings = JobSettings {
+        seed: options.seed,
+        sampler: options.sampler,
+        aovs: options.captures_aovs(),
+        adaptive: options.adaptive,
+        spectral: options.spectral,
+    };
+    let queue = Arc::new(TileQueue::new(tiles));
+    let (results, received) = mpsc::channel();
+
+    // workers may come and go during the render, each one is served by its
+    // own thread
+    let accept = {
+        let queue = queue.clone();
+        let callback = options.progress.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                // the connection that wakes the loop up once the render is
+                // over, dropping the listener frees the port
+                if queue.is_finished() {
+                    break;
+                }
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let queue = queue.clone();
+                let results = results.clone();
+                let callback = callback.clone();
+                thread::spawn(move || {
+                    let peer = stream
+                        .peer_addr()
+                        .map_or_else(|_| "unknown".to_owned(), |addr| addr.to_string());
+                    if let Err(e) = serve_worker(stream, settings, scene_hash, &queue, &results) {
+                        progress::report(callback.as_ref(), || ProgressEvent::WorkerDropped {
+                            peer,
+                            error: e.to_string(),
+                        });
+                    }
+                });
+            }
+        })
+    };
+
+    let start_time = Instant::now();
+    let mut film = Film::new(width, height, settings.aovs);
+    let progress = Progress::new(total_tiles, callback);
+
+    for _ in 0..total_tiles {
+        // the listener thread keeps a sender alive, so this can't fail
+        let (tile, pixels, rays): (Tile, TilePixels, u64) =
+            received.recv().expect("Tile results channel closed");
+        for ((i, j), (stats, aov)) in tile.pixels().zip(pixels) {
+            film.pixels_mut()[j * width + i] = stats;
+            if let Some(aov) = aov {
+                film.aovs_mut()[j * width + i] = aov;
+            }
+        }
+        progress.tile_done(rays);
+    }
+    queue.finish();
+    // without the wake-up the accept loop would block forever, the thread
+    // is left behind then
+    if TcpStream::connect_timeout(&loopback(addr), CONNECT_TIMEOUT).is_ok() {
+        accept.join().expect("Listener thread panicked");
+    }
+
+    progress::report(callback, || ProgressEvent::Finished {
+        elapsed: start_time.elapsed(),
+        rays: progress.rays(),
+        stopped: false,
+        average_samples: film.average_samples(),
+        unsampled_pixels: 0,
+    });
+
+    Ok(film)
+}
+
+/// Address to reach a listener bound to `addr` from the same machine.
+fn loopback(mut addr: SocketAddr) -> SocketAddr {
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr {
+            SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+            SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+        });
+    }
+    addr
+}
+
+/// Hand tiles to one worker until there are none left. A tile the worker
+/// doesn't return goes back into the queue for the others.
+fn serve_worker(
+    stream: TcpStream,
+    settings: JobSettings,
+    scene_hash: u64,
+    queue: &TileQueue,
+    results: &mpsc::Sender<(Tile, TilePixels, u64)>,
+) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut r = BufReader::new(stream.try_clone()?);
+    let mut w = BufWriter::new(stream);
+
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC || read_u32(&mut r)? != VERSION {
+        return Err(checkpoint::invalid("not an rt-rs worker"));
+    }
+    if read_u64(&mut r)? != scene_hash {
+        w.write_all(&[0])?;
+        w.flush()?;
+        return Err(checkpoint::invalid("worker loaded a different scene"));
+    }
+    w.write_all(&[1])?;
+    settings.write_to(&mut w)?;
+    w.flush()?;
+
+    loop {
+        // idle workers wait for tiles that others fail to return
+        let Some(tile) = queue.pop() else {
+            w.write_all(&[0])?;
+            return w.flush();
+        };
+
+        match render_remote(&mut r, &mut w, &tile, settings.aovs) {
+            Ok((pixels, rays)) => {
+                // the coordinator only stops listening once every tile is in
+                results.send((tile, pixels, rays)).ok();
+            }
+            Err(e) => {
+                queue.requeue(tile);
+                return Err(e);
+            }
+        }
+    }
+}
+
+fn render_remote(
+    r: &mut impl Read,
+    w: &mut impl Write,
+    tile: &Tile,
+    aovs: bool,
+) -> io::Result<(TilePixels, u64)> {
+    w.write_all(&[1])?;
+    for value in [tile.x.start, tile.x.end, tile.y.start, tile.y.end] {
+        write_u32(w, value as u32)?;
+    }
+    w.flush()?;
+
+    let rays = read_u64(r)?;
+    let pixels = tile
+        .pixels()
+        .map(|_| {
+            let stats = PixelStats::read_from(r)?;
+            let aov = if aovs {
+                Some(AovPixel::read_from(r)?)
+            } else {
+                None
+            };
+            Ok((stats, aov))
+        })
+        .collect::<io::Result<_>>()?;
+    Ok((pixels, rays))
+}
+
+/// Render tiles for the coordinator at `connect` over `connections`
+/// parallel connections until it has none left, returns the number of
+/// tiles rendered. Fails with the error of the first connection that
+/// failed, once all of them are done.
+pub fn work(
+    connect: &str,
+    camera: &Camera,
+    world: &dyn Hittable,
+    scene_hash: u64,
+    connections: usize,
+) -> error::Result<usize> {
+    let results: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..connections)
+            .map(|_| scope.spawn(|| work_connection(connect, camera, world, scene_hash)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Worker thread panicked"))
+            .collect()
+    });
+
+    let mut tiles = 0;
+    for result in results {
+        tiles += result?;
+    }
+    Ok(tiles)
+}
+
+/// Serve one connection, returns the number of tiles rendered.
+fn work_connection(
+    connect: &str,
+    camera: &Camera,
+    world: &dyn Hittable,
+    scene_hash: u64,
+) -> io::Result<usize> {
+    let start = Instant::now();
+    let stream = loop {
+        match TcpStream::connect(connect) {
+            Ok(stream) => break stream,
+            Err(e) if start.elapsed() >= CONNECT_TIMEOUT => return Err(e),
+            Err(_) => thread::sleep(Duration::from_millis(200)),
+        }
+    };
+    stream.set_nodelay(true)?;
+    let mut r = BufReader::new(stream.try_clone()?);
+    let mut w = BufWriter::new(stream);
+
+    w.write_all(MAGIC)?;
+    write_u32(&mut w, VERSION)?;
+    write_u64(&mut w, scene_hash)?;
+    w.flush()?;
+
+    let mut accepted = [0];
+    r.read_exact(&mut accepted)?;
+    if accepted[0] == 0 {
+        return Err(checkpoint::invalid(
+            "the coordinator renders a different scene",
+        ));
+    }
+    let settings = JobSettings::read_from(&mut r)?;
+    let options = RenderOptions {
+        seed: settings.seed,
+        sampler: settings.sampler,
+        adaptive: settings.adaptive,
+        spectral: settings.spectral,
+        // only whether AOVs are captured matters here
+        aovs: if settings.aovs {
+            AovKind::ALL.to_vec()
+        } else {
+            Vec::new()
+        },
+        ..RenderOptions::default()
+    };
+
+    let mut tiles = 0;
+    loop {
+        // the coordinator may also just exit once it has every tile
+        let mut tag = [0];
+        match r.read_exact(&mut tag) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(tiles),
+            result => result?,
+        }
+        if tag[0] == 0 {
+            return Ok(tiles);
+        }
+        let x0 = read_u32(&mut r)? as usize;
+        let x1 = read_u32(&mut r)? as usize;
+        let y0 = read_u32(&mut r)? as usize;
+        let y1 = read_u32(&mut r)? as usize;
+        let tile = Tile {
+            x: x0..x1,
+            y: y0..y1,
+        };
+
+        let mut pixels: TilePixels = tile
+            .pixels()
+            .map(|_| (PixelStats::default(), settings.aovs.then(AovPixel::default)))
+            .collect();
+        let rays = camera.render_tile(
+            world,
+            &options,
+            &tile,
+            &mut pixels,
+            camera.samples_per_pixel(),
+            &Stop::default(),
+        );
+
+        write_u64(&mut w, rays)?;
+        for (stats, aov) in &pixels {
+            stats.write_to(&mut w)?;
+            if let Some(aov) = aov {
+                aov.write_to(&mut w)?;
+            }
+        }
+        w.flush()?;
+        tiles += 1;
+    }
+}
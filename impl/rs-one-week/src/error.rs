@@ -0,0 +1,81 @@
+use std::{fmt, io};
+
+use crate::scene::SceneError;
+
+/// Everything that can go wrong while setting up, rendering or saving an
+/// image.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Image(image::ImageError),
+    Exr(exr::error::Error),
+    Scene(SceneError),
+    /// camera parameters that don't describe an image
+    InvalidCamera(&'static str),
+    /// material parameters that don't describe a surface
+    InvalidMaterial(&'static str),
+    /// a checkpoint that doesn't belong to the render resuming it
+    Checkpoint(String),
+    /// render options that don't work together
+    InvalidOptions(&'static str),
+    /// command line arguments that are missing a value or can't be parsed
+    Usage(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "i/o error: {e}"),
+            Error::Image(e) => write!(f, "image error: {e}"),
+            Error::Exr(e) => write!(f, "exr error: {e}"),
+            Error::Scene(e) => write!(f, "scene error: {e}"),
+            Error::InvalidCamera(msg) => write!(f, "invalid camera: {msg}"),
+            Error::InvalidMaterial(msg) => write!(f, "invalid material: {msg}"),
+            Error::Checkpoint(msg) => write!(f, "checkpoint error: {msg}"),
+            Error::InvalidOptions(msg) => write!(f, "invalid options: {msg}"),
+            Error::Usage(msg) => write!(f, "invalid arguments: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Image(e) => Some(e),
+            Error::Exr(e) => Some(e),
+            Error::Scene(e) => Some(e),
+            Error::InvalidCamera(_)
+            | Error::InvalidMaterial(_)
+            | Error::Checkpoint(_)
+            | Error::InvalidOptions(_)
+            | Error::Usage(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(e: image::ImageError) -> Self {
+        Error::Image(e)
+    }
+}
+
+impl From<exr::error::Error> for Error {
+    fn from(e: exr::error::Error) -> Self {
+        Error::Exr(e)
+    }
+}
+
+impl From<SceneError> for Error {
+    fn from(e: SceneError) -> Self {
+        Error::Scene(e)
+    }
+}
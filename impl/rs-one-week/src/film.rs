@@ -0,0 +1,202 @@
+use std::io::{self, Read, Write};
+
+use crate::{
+    aov::{self, AovKind, AovPixel},
+    checkpoint,
+    color::Color,
+    denoise::{self, DenoiseOptions},
+    framebuffer::FrameBuffer,
+    output::ExrLayer,
+    tonemap,
+};
+
+/// Running statistics of the samples taken for one pixel.
+///
+/// Besides the radiance sum it tracks the variance of the luminance with
+/// Welford's algorithm, which is what adaptive sampling bases its stopping
+/// decision on.
+#[derive(Clone, Copy, Debug)]
+pub struct PixelStats {
+    sum: Color,
+    count: u32,
+    mean: f64,
+    m2: f64,
+}
+
+impl Default for PixelStats {
+    fn default() -> Self {
+        Self {
+            sum: Color::ZERO,
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+}
+
+impl PixelStats {
+    pub fn add(&mut self, color: Color) {
+        self.sum += color;
+        self.count += 1;
+
+        let l = tonemap::luminance(color);
+        let delta = l - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (l - self.mean);
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// average radiance, black for pixels without samples
+    pub fn mean(&self) -> Color {
+        if self.count == 0 {
+            return Color::ZERO;
+        }
+        self.sum / self.count as f64
+    }
+
+    pub fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        let [r, g, b] = self.sum.to_array();
+        checkpoint::write_f64s(w, &[r, g, b, self.mean, self.m2])?;
+        checkpoint::write_u32(w, self.count)
+    }
+
+    pub fn read_from(r: &mut impl Read) -> io::Result<Self> {
+        let [sr, sg, sb, mean, m2] = checkpoint::read_f64s(r)?;
+        Ok(Self {
+            sum: Color::new(sr, sg, sb),
+            count: checkpoint::read_u32(r)?,
+            mean,
+            m2,
+        })
+    }
+
+    /// Estimated noise of the mean after display encoding.
+    ///
+    /// The standard error of the mean luminance is divided by the slope of a
+    /// gamma 2 curve, so the same amount of noise counts more in the shadows
+    /// than on bright surfaces, roughly like the eye sees it.
+    pub fn error(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
+        }
+        let variance = self.m2 / (self.count - 1) as f64;
+        let std_error = (variance / self.count as f64).sqrt();
+        std_error / (2.0 * self.mean.max(1e-4).sqrt())
+    }
+}
+
+/// Per-pixel sample accumulation for a whole image.
+#[derive(Clone)]
+pub struct Film {
+    width: usize,
+    height: usize,
+    pixels: Vec<PixelStats>,
+    /// empty unless the render captures AOVs
+    aovs: Vec<AovPixel>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize, with_aovs: bool) -> Self {
+        let aov_count = if with_aovs { width * height } else { 0 };
+        Self {
+            width,
+            height,
+            pixels: vec![PixelStats::default(); width * height],
+            aovs: vec![AovPixel::default(); aov_count],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[PixelStats] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [PixelStats] {
+        &mut self.pixels
+    }
+
+    /// Samples per pixel, averaged over the image.
+    pub fn average_samples(&self) -> f64 {
+        let total = self.pixels.iter().map(|p| p.count() as u64).sum::<u64>();
+        total as f64 / self.pixels.len().max(1) as f64
+    }
+
+    pub fn aovs(&self) -> &[AovPixel] {
+        &self.aovs
+    }
+
+    pub fn aovs_mut(&mut self) -> &mut [AovPixel] {
+        &mut self.aovs
+    }
+
+    /// image layers for the requested AOVs, none if they weren't captured
+    pub fn aov_layers(&self, kinds: &[AovKind]) -> Vec<ExrLayer> {
+        if self.aovs.is_empty() {
+            return Vec::new();
+        }
+        kinds
+            .iter()
+            .map(|kind| aov::layer(*kind, &self.aovs))
+            .collect()
+    }
+
+    /// the current estimate of every pixel
+    pub fn frame_buffer(&self) -> FrameBuffer {
+        let mut fb = FrameBuffer::new(self.width, self.height);
+        fb.pixels_mut()
+            .iter_mut()
+            .zip(self.pixels.iter())
+            .for_each(|(out, stats)| *out = stats.mean());
+        fb
+    }
+
+    /// The final image, filtered with the AOVs when `denoise` is given. A
+    /// film without AOVs has nothing to guide the filter and stays as it
+    /// is.
+    pub fn image(&self, denoise: Option<&DenoiseOptions>) -> FrameBuffer {
+        let fb = self.frame_buffer();
+        match denoise {
+            Some(options) if !self.aovs.is_empty() => denoise::denoise(&fb, &self.aovs, options),
+            _ => fb,
+        }
+    }
+
+    /// Number of samples per pixel as a blue (fewest) to red (most) heat map.
+    pub fn sample_heatmap(&self) -> FrameBuffer {
+        let max = self
+            .pixels
+            .iter()
+            .map(|p| p.count)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+
+        let mut fb = FrameBuffer::new(self.width, self.height);
+        fb.pixels_mut()
+            .iter_mut()
+            .zip(self.pixels.iter())
+            .for_each(|(out, stats)| *out = heat(stats.count as f64 / max as f64));
+        fb
+    }
+}
+
+fn heat(t: f64) -> Color {
+    // blue -> cyan -> green -> yellow -> red
+    let t = t.clamp(0.0, 1.0) * 4.0;
+    match t {
+        t if t < 1.0 => Color::new(0.0, t, 1.0),
+        t if t < 2.0 => Color::new(0.0, 1.0, 2.0 - t),
+        t if t < 3.0 => Color::new(t - 2.0, 1.0, 0.0),
+        t => Color::new(1.0, 4.0 - t, 0.0),
+    }
+}
@@ -0,0 +1,56 @@
+use crate::{
+    color::{self, Color},
+    tonemap::ToneMapping,
+};
+
+/// Linear, unclamped RGB radiance for every pixel of a render.
+pub struct FrameBuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl FrameBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Color::ZERO; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+
+    /// pixels in row-major order, top row first
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [Color] {
+        &mut self.pixels
+    }
+
+    /// Tone map and quantize to 8-bit sRGB, three bytes per pixel in
+    /// row-major order.
+    pub fn to_rgb8(&self, tone_mapping: &ToneMapping) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|pixel| color::get_pixel(*pixel, tone_mapping))
+            .collect()
+    }
+
+    /// one channel of every pixel as `f32`, handy for float image writers
+    pub fn channel(&self, c: usize) -> Vec<f32> {
+        self.pixels.iter().map(|p| p[c] as f32).collect()
+    }
+}
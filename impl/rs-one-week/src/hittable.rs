@@ -2,48 +2,113 @@ use std::ops::Range;
 
 use crate::{
     material::Material,
-    ray::Ray,
-    vec3::{self, Point3, SliceOp, Vec3},
+    ray::{LANES, Ray, RayPacket},
+    vec3::{Float, Point3, Vec3},
 };
 
-#[derive(Clone)]
-pub struct HitRecord {
+/// Where and what a ray hit.
+#[derive(Clone, Copy)]
+pub struct HitRecord<'a> {
     pub p: Point3,
+    /// bound on the rounding error of every coordinate of `p`, the true
+    /// surface point is at most this far away
+    pub p_error: Float,
     pub normal: Vec3,
-    pub t: f64,
+    pub t: Float,
+    /// maps the outward normal to the surface coordinates, which are only
+    /// worked out on demand as most hits get replaced by a closer one
+    uv: fn(Vec3) -> (Float, Float),
     pub front_face: bool,
+    /// index of the object in the outermost list that was hit
+    pub object_id: usize,
+    pub material: &'a dyn Material,
 }
 
-impl HitRecord {
-    pub fn init() -> Self {
-        Self {
-            p: vec3::init(),
-            normal: vec3::init(),
-            t: 0.0,
-            front_face: false,
-        }
-    }
-
-    pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: Vec3) {
-        self.front_face = ray.direction().dot(outward_normal) < 0.0;
-        self.normal = if self.front_face {
+impl<'a> HitRecord<'a> {
+    /// Hit of `ray` at `t`, the normal is flipped to face the ray. `uv`
+    /// gives the surface coordinates for an outward normal.
+    pub fn new(
+        ray: &Ray,
+        t: Float,
+        outward_normal: Vec3,
+        uv: fn(Vec3) -> (Float, Float),
+        material: &'a dyn Material,
+    ) -> Self {
+        let front_face = ray.direction().dot(outward_normal) < 0.0;
+        let normal = if front_face {
             // ray is outside the sphere
             outward_normal
         } else {
             // ray is inside the sphere
-            outward_normal.neg()
+            -outward_normal
+        };
+
+        let p = ray.at(t);
+        Self {
+            p,
+            p_error: rounding_error(p),
+            normal,
+            t,
+            uv,
+            front_face,
+            object_id: 0,
+            material,
         }
     }
+
+    /// Surface coordinates of `p`, both in `0..1`.
+    pub fn uv(&self) -> (Float, Float) {
+        let outward_normal = if self.front_face {
+            self.normal
+        } else {
+            -self.normal
+        };
+        (self.uv)(outward_normal)
+    }
+
+    /// `scattered` leaving the surface, with its origin pushed along the
+    /// normal past the error of `p` to the side it heads to. Otherwise the
+    /// rounding error lets it hit the surface it leaves from, for large
+    /// and distant surfaces in `f32` especially.
+    pub fn spawn(&self, scattered: Ray) -> Ray {
+        let n = self.normal;
+        let distance = self.p_error * (n.x().abs() + n.y().abs() + n.z().abs());
+        let offset = if scattered.direction().dot(n) < 0.0 {
+            -n * distance
+        } else {
+            n * distance
+        };
+        Ray::new(self.p + offset, *scattered.direction()).with_wavelength(scattered.wavelength())
+    }
 }
 
-pub trait Hittable<'m>: Send + Sync {
-    fn hit(&self, r: &Ray, ray_t: Range<f64>, record: &mut HitRecord) -> Option<&'m Material>;
+/// Bound on the rounding error of a point computed from values as large as
+/// `p`, a few ulps of its largest coordinate.
+pub fn rounding_error(p: Point3) -> Float {
+    let [x, y, z] = p.to_array();
+    4.0 * Float::EPSILON * x.abs().max(y.abs()).max(z.abs())
+}
+
+pub trait Hittable: Send + Sync {
+    /// The closest hit of `r` in `ray_t`.
+    fn hit(&self, r: &Ray, ray_t: Range<Float>) -> Option<HitRecord<'_>>;
+
+    /// `hit` for every ray of `packet`, lane `i` only accepts hits in
+    /// `t_min..t_max[i]`. The default traces the rays one by one.
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        t_min: Float,
+        t_max: [Float; LANES],
+    ) -> [Option<HitRecord<'_>>; LANES] {
+        std::array::from_fn(|i| self.hit(&packet.rays[i], t_min..t_max[i]))
+    }
 }
 
 #[repr(transparent)]
 pub struct HittableList<H>(Vec<H>);
 
-impl<'m, H: Hittable<'m>> HittableList<H> {
+impl<H: Hittable> HittableList<H> {
     pub fn new() -> Self {
         Self(Vec::new())
     }
@@ -53,7 +118,13 @@ impl<'m, H: Hittable<'m>> HittableList<H> {
     }
 }
 
-impl<'m, H: Hittable<'m>> IntoIterator for HittableList<H> {
+impl<H: Hittable> Default for HittableList<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H: Hittable> IntoIterator for HittableList<H> {
     type Item = H;
 
     type IntoIter = std::vec::IntoIter<H>;
@@ -63,20 +134,46 @@ impl<'m, H: Hittable<'m>> IntoIterator for HittableList<H> {
     }
 }
 
-impl<'m, H: Hittable<'m>> Hittable<'m> for HittableList<H> {
-    fn hit(&self, r: &Ray, ray_t: Range<f64>, record: &mut HitRecord) -> Option<&'m Material> {
-        let mut temp_rec = HitRecord::init();
+impl<H: Hittable> Hittable for HittableList<H> {
+    fn hit(&self, r: &Ray, ray_t: Range<Float>) -> Option<HitRecord<'_>> {
+        let mut closest = None;
         let mut closest_so_far = ray_t.end;
-        let mut material_hit = None;
 
-        for obj in self.0.iter() {
-            if let Some(m) = obj.hit(r, ray_t.start..closest_so_far, &mut temp_rec) {
-                material_hit = Some(m);
-                closest_so_far = temp_rec.t;
-                *record = temp_rec.clone();
+        for (id, obj) in self.0.iter().enumerate() {
+            if let Some(record) = obj.hit(r, ray_t.start..closest_so_far) {
+                closest_so_far = record.t;
+                closest = Some(HitRecord {
+                    object_id: id,
+                    ..record
+                });
+            }
+        }
+
+        closest
+    }
+
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        t_min: Float,
+        t_max: [Float; LANES],
+    ) -> [Option<HitRecord<'_>>; LANES] {
+        let mut closest = [None; LANES];
+        let mut closest_so_far = t_max;
+
+        for (id, obj) in self.0.iter().enumerate() {
+            let hits = obj.hit_packet(packet, t_min, closest_so_far);
+            for (i, record) in hits.iter().enumerate() {
+                if let Some(record) = record {
+                    closest_so_far[i] = record.t;
+                    closest[i] = Some(HitRecord {
+                        object_id: id,
+                        ..*record
+                    });
+                }
             }
         }
 
-        material_hit
+        closest
     }
 }
@@ -0,0 +1,67 @@
+//! A CPU path tracer following "Ray Tracing in One Weekend".
+//!
+//! Build a [`Scene`] in code or parse one from a scene file, turn its
+//! [`CameraSettings`] into a [`Camera`] and render the scene's world with
+//! [`RenderOptions`].
+//!
+//! The `simd` feature keeps vectors in SIMD registers, `f32` traces the
+//! geometry in single precision, see [`vec3::Float`].
+
+/// Per-pixel first-hit data (albedo, normal, depth, ...) captured next to
+/// the image.
+pub mod aov;
+/// The camera model and the render loop.
+pub mod camera;
+/// Stopping renders early.
+pub mod cancel;
+/// Saving and resuming the state of a render.
+pub mod checkpoint;
+/// Colors and their conversion to 8-bit sRGB.
+pub mod color;
+/// Edge-avoiding denoiser guided by the AOVs.
+pub mod denoise;
+/// Rendering a frame on several processes over TCP.
+pub mod distributed;
+/// The error type of the renderer.
+pub mod error;
+/// Accumulated samples of every pixel.
+pub mod film;
+/// Float RGB images.
+pub mod framebuffer;
+/// Ray-object intersection.
+pub mod hittable;
+/// Surface materials.
+pub mod material;
+/// Microfacet distributions and Fresnel terms shared by the rough
+/// materials.
+pub mod microfacet;
+/// Writing images to PNG, PPM, HDR and EXR files.
+pub mod output;
+/// Reporting what a running render is doing.
+pub mod progress;
+/// Rays and packets of rays traced together.
+pub mod ray;
+/// Sample sequences for pixels, lenses and scattering.
+pub mod sampler;
+/// Scene description and the scene file format.
+pub mod scene;
+/// Wavelength sampling and conversions between spectra and RGB.
+pub mod spectrum;
+/// Spheres.
+pub mod sphere;
+/// Splitting the image into tiles of work.
+pub mod tile;
+/// Mapping HDR radiance to displayable values.
+pub mod tonemap;
+/// Small math and random number helpers.
+pub mod util;
+/// 3D vectors.
+pub mod vec3;
+
+pub use camera::{Camera, RenderOptions};
+pub use error::{Error, Result};
+pub use framebuffer::FrameBuffer;
+pub use hittable::{Hittable, HittableList};
+pub use material::{ComplexIor, Conductor, Dielectric, Dispersion, Lambertian, Material, Metal};
+pub use scene::{CameraSettings, Scene};
+pub use sphere::Sphere;
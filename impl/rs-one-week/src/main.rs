@@ -1,79 +1,339 @@
-use vec3::SliceOp;
+use std::{
+    fmt,
+    io::{self, Write},
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
 
-mod camera;
-mod color;
-mod hittable;
-mod material;
-mod ray;
-mod sphere;
-mod util;
-mod vec3;
+use rt_rs::{
+    Error, RenderOptions, Result, Scene, aov,
+    camera::CheckpointOptions,
+    distributed,
+    film::Film,
+    output::{self, ExrPrecision, OutputOptions},
+    progress::{ProgressCallback, ProgressEvent},
+};
 
-fn main() {
-    // collect random materials
-    let mut materials = Vec::new();
-    for a in -9..10 {
-        for b in -9..10 {
-            let random_mat = util::random_float();
-            let center = [
-                a as f64 + 0.9 * util::random_float(),
-                0.2,
-                b as f64 + 0.9 * util::random_float(),
-            ];
-
-            let diff = center.sub([4.0, 0.2, 0.0]);
-            if diff.length() > 0.9 {
-                let material = match random_mat {
-                    x if x < 0.8 => {
-                        let albedo = vec3::random().mul(vec3::random());
-                        material::Material::Lambertian(albedo)
-                    }
-                    x if x < 0.95 => {
-                        let albedo = vec3::random_min_max(0.5, 1.0);
-                        let fuzz = util::random_min_max(0.0, 0.5);
-                        material::Material::Metal(albedo, fuzz)
-                    }
-                    _ => material::Material::Dielectric(1.5),
-                };
-
-                materials.push((center, material));
+/// What this process does with the scene.
+enum Mode {
+    Render,
+    /// hand out tiles to workers connecting to this address
+    Coordinator(String),
+    /// render tiles for the coordinator at this address
+    Worker(String),
+}
+
+struct Args {
+    mode: Mode,
+    scene: Option<String>,
+    render_options: RenderOptions,
+    threads: Option<usize>,
+    outputs: Vec<String>,
+    output_options: OutputOptions,
+}
+
+/// The arguments after the program name, which know the flag they follow
+/// for the error messages.
+struct ArgIter(std::iter::Skip<std::env::Args>);
+
+impl ArgIter {
+    /// The value after `flag`, described as `what` if it is missing.
+    fn value(&mut self, flag: &str, what: &str) -> Result<String> {
+        self.0
+            .next()
+            .ok_or_else(|| Error::Usage(format!("missing {what} after {flag}")))
+    }
+
+    fn parse<T: FromStr<Err: fmt::Display>>(&mut self, flag: &str, what: &str) -> Result<T> {
+        let value = self.value(flag, what)?;
+        value
+            .parse()
+            .map_err(|e| Error::Usage(format!("invalid {what} {value:?} after {flag}: {e}")))
+    }
+
+    fn seconds(&mut self, flag: &str) -> Result<Duration> {
+        let seconds = self.parse(flag, "seconds")?;
+        Duration::try_from_secs_f64(seconds).map_err(|_| {
+            Error::Usage(format!(
+                "{flag} needs a positive number of seconds, not {seconds}"
+            ))
+        })
+    }
+}
+
+fn parse_args() -> Result<Args> {
+    let mut args = Args {
+        mode: Mode::Render,
+        scene: None,
+        render_options: RenderOptions::default(),
+        threads: None,
+        outputs: Vec::new(),
+        output_options: OutputOptions::default(),
+    };
+
+    let mut checkpoint_path = None;
+    let mut checkpoint_interval = Duration::from_secs(300);
+
+    let mut argv = ArgIter(std::env::args().skip(1));
+    while let Some(arg) = argv.0.next() {
+        let flag = arg.as_str();
+        match flag {
+            "-o" | "--output" => args.outputs.push(argv.value(flag, "file name")?),
+            "--scene" => args.scene = Some(argv.value(flag, "file name")?),
+            "--listen" => args.mode = Mode::Coordinator(argv.value(flag, "address")?),
+            "--connect" => args.mode = Mode::Worker(argv.value(flag, "address")?),
+            "--seed" => args.render_options.seed = argv.parse(flag, "seed")?,
+            "--sampler" => args.render_options.sampler = argv.parse(flag, "sampler")?,
+            "--adaptive" => {
+                let threshold = argv.parse(flag, "threshold")?;
+                args.render_options
+                    .adaptive
+                    .get_or_insert_default()
+                    .threshold = threshold;
+            }
+            "--min-samples" => {
+                let min_samples = argv.parse(flag, "sample count")?;
+                args.render_options
+                    .adaptive
+                    .get_or_insert_default()
+                    .min_samples = min_samples;
+            }
+            "--progressive" => {
+                args.render_options.progressive = Some(argv.parse(flag, "samples per pass")?);
+            }
+            "--tile-size" => args.render_options.tile_size = argv.parse(flag, "tile size")?,
+            "--tile-order" => args.render_options.tile_order = argv.parse(flag, "tile order")?,
+            "--aov" => {
+                let value = argv.value(flag, "AOV list")?;
+                args.render_options.aovs =
+                    aov::AovKind::parse_list(&value).map_err(Error::Usage)?;
+            }
+            "--denoise" => {
+                args.render_options.denoise.get_or_insert_default();
+            }
+            "--denoise-iterations" => {
+                let iterations = argv.parse(flag, "iteration count")?;
+                args.render_options
+                    .denoise
+                    .get_or_insert_default()
+                    .iterations = iterations;
+            }
+            "--checkpoint" => checkpoint_path = Some(argv.value(flag, "file name")?),
+            "--checkpoint-interval" => checkpoint_interval = argv.seconds(flag)?,
+            "--resume" => args.render_options.resume = Some(argv.value(flag, "file name")?),
+            "--time-budget" => args.render_options.time_budget = Some(argv.seconds(flag)?),
+            "--no-ray-packets" => args.render_options.ray_packets = false,
+            "--spectral" => args.render_options.spectral = true,
+            "--heatmap" => {
+                args.output_options.sample_heatmap = Some(argv.value(flag, "file name")?)
             }
+            "--threads" => args.threads = Some(argv.parse(flag, "thread count")?),
+            "--exr-float" => args.output_options.exr_precision = ExrPrecision::Float,
+            "--tonemap" => {
+                args.output_options.tone_mapping.operator = argv.parse(flag, "operator")?;
+            }
+            "--exposure" => args.output_options.tone_mapping.exposure = argv.parse(flag, "EV")?,
+            _ => return Err(Error::Usage(format!("unknown argument {arg}"))),
         }
     }
 
-    // world
-    let mut world = hittable::HittableList::new();
+    args.render_options.checkpoint = checkpoint_path.map(|path| CheckpointOptions {
+        path,
+        interval: checkpoint_interval,
+    });
+    args.render_options.progress = Some(print_progress(args.render_options.adaptive.is_some()));
 
-    let material_ground = material::Material::Lambertian([0.5, 0.5, 0.5]);
-    world.push(sphere::Sphere::new(
-        [0.0, -1000.0, 0.0],
-        1000.0,
-        &material_ground,
-    ));
+    // keep the old behaviour when no output is given
+    if args.outputs.is_empty() {
+        args.outputs.push("image.png".to_owned());
+    }
+    Ok(args)
+}
 
-    materials.iter().for_each(|(center, mat)| {
-        world.push(sphere::Sphere::new(*center, 0.2, mat));
+fn main() {
+    let result = parse_args().and_then(|args| {
+        if let Some(threads) = args.threads {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build_global()
+                .expect("Failed to set up the thread pool");
+        }
+        run(&args)
     });
 
-    let mat_dielectric = material::Material::Dielectric(1.5);
-    let mat_lambertian = material::Material::Lambertian([0.4, 0.2, 0.1]);
-    let mat_metal = material::Material::Metal([0.7, 0.6, 0.5], 0.0);
-
-    world.push(sphere::Sphere::new([0.0, 1.0, 0.0], 1.0, &mat_dielectric));
-    world.push(sphere::Sphere::new([-4.0, 1.0, 0.0], 1.0, &mat_lambertian));
-    world.push(sphere::Sphere::new([4.0, 1.0, 0.0], 1.0, &mat_metal));
-
-    let cam = camera::Camera::new(
-        16.0 / 9.0,
-        1920,
-        64,
-        16,
-        24.0,
-        [13.0, 2.0, 5.0],
-        vec3::init(),
-        [0.0, 1.0, 0.0],
-        0.6,
-        10.0,
-    );
-    cam.render(&world);
+    if let Err(e) = result {
+        eprintln!("error: {e}");
+        std::process::exit(1);
+    }
+}
+
+fn run(args: &Args) -> Result<()> {
+    let (scene, scene_hash) = match &args.scene {
+        Some(path) => {
+            let source = std::fs::read_to_string(path)?;
+            (
+                Scene::parse(&source)?,
+                Some(distributed::scene_hash(&source)),
+            )
+        }
+        // the scene layout is random too, derive it from the same seed
+        None => (Scene::cover(args.render_options.seed), None),
+    };
+    let world = scene.world();
+    let cam = scene.camera.camera()?;
+
+    let distributed_scene = || {
+        scene_hash.ok_or(Error::InvalidOptions(
+            "distributed rendering needs a --scene file",
+        ))
+    };
+
+    let film = match &args.mode {
+        // keep the snapshot on disk up to date, so the render can be
+        // stopped as soon as it looks good enough
+        Mode::Render => cam.render_film(&world, &args.render_options, |film| {
+            write_outputs(film, args)
+        })?,
+        Mode::Coordinator(listen) => {
+            distributed::coordinate(listen, &cam, distributed_scene()?, &args.render_options)?
+        }
+        Mode::Worker(connect) => {
+            println!("Connecting to {connect} ...");
+            let tiles = distributed::work(
+                connect,
+                &cam,
+                &world,
+                distributed_scene()?,
+                rayon::current_num_threads(),
+            )?;
+            println!("Rendered {tiles} tiles");
+            return Ok(());
+        }
+    };
+
+    write_outputs(&film, args)?;
+    println!("Done");
+    Ok(())
+}
+
+/// Write the film to every output, denoised first if asked to.
+fn write_outputs(film: &Film, args: &Args) -> Result<()> {
+    let options = &args.render_options;
+    if options.denoise.is_some() {
+        println!("Denoising ...");
+    }
+    let image = film.image(options.denoise.as_ref());
+
+    for path in &args.outputs {
+        println!("Writing pixels to {path}");
+        let layer_paths = output::save(
+            path,
+            &image,
+            film.aov_layers(&options.aovs),
+            &args.output_options,
+        )?;
+        for layer_path in layer_paths {
+            println!("Wrote {}", layer_path.display());
+        }
+    }
+    if let Some(path) = &args.output_options.sample_heatmap {
+        println!("Writing sample counts to {path}");
+        output::save(
+            path,
+            &film.sample_heatmap(),
+            Vec::new(),
+            &OutputOptions::default(),
+        )?;
+    }
+    Ok(())
+}
+
+/// Print what the render is doing, the tile counter stays on one line of
+/// stderr until something else is printed.
+fn print_progress(adaptive: bool) -> ProgressCallback {
+    let tile_line = AtomicBool::new(false);
+    ProgressCallback::new(move |event| {
+        if let ProgressEvent::Tiles {
+            done,
+            total,
+            rays_per_sec,
+            eta,
+        } = event
+        {
+            eprint!(
+                "\rtiles {done}/{total} ({:5.1}%)  {:.2} Mrays/s  ETA {}   ",
+                100.0 * *done as f64 / (*total).max(1) as f64,
+                rays_per_sec / 1e6,
+                format_duration(*eta)
+            );
+            io::stderr().flush().ok();
+            tile_line.store(true, Ordering::Relaxed);
+            return;
+        }
+        if tile_line.swap(false, Ordering::Relaxed) {
+            eprintln!();
+        }
+
+        match event {
+            ProgressEvent::Started {
+                width,
+                height,
+                resumed,
+            } => {
+                println!("Starting render...\n");
+                println!("Resolution: {width} x {height} = {}", width * height);
+                if let Some(path) = resumed {
+                    println!("Resuming from {path}");
+                }
+                println!("Rendering ...");
+            }
+            ProgressEvent::PassDone {
+                pass,
+                passes,
+                samples_per_pixel,
+                elapsed,
+            } => println!(
+                "pass {pass}/{passes}: {samples_per_pixel} samples per pixel, elapsed: {elapsed:?}"
+            ),
+            ProgressEvent::CheckpointFailed { error, .. } => {
+                eprintln!("Failed to write checkpoint: {error}");
+            }
+            ProgressEvent::WritingCheckpoint { path } => println!("Writing checkpoint to {path}"),
+            ProgressEvent::Finished {
+                elapsed,
+                rays,
+                stopped,
+                average_samples,
+                unsampled_pixels,
+            } => {
+                println!(
+                    "elapsed: {elapsed:?}, {:.2} Mrays/s",
+                    *rays as f64 / elapsed.as_secs_f64() / 1e6
+                );
+                if *stopped {
+                    println!("Stopped early");
+                }
+                if adaptive || *stopped {
+                    println!("average samples per pixel: {average_samples:.2}");
+                }
+                if *unsampled_pixels > 0 {
+                    println!("{unsampled_pixels} pixels have no samples");
+                }
+            }
+            ProgressEvent::Listening { addr } => {
+                println!("Waiting for workers on {addr}");
+                println!("Rendering ...");
+            }
+            ProgressEvent::WorkerDropped { peer, error } => {
+                eprintln!("worker {peer} dropped: {error}");
+            }
+            ProgressEvent::Tiles { .. } => {}
+        }
+    })
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs_f64().round() as u64;
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
 }
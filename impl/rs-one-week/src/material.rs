@@ -1,77 +1,525 @@
+use std::f64::consts::PI;
+
 use crate::{
     color::Color,
+    error::{Error, Result},
     hittable::HitRecord,
+    microfacet::{self, Frame, Ggx},
     ray::Ray,
     util,
-    vec3::{self, SliceOp},
+    vec3::{self, Float, Vec3},
 };
 
-pub enum Material {
-    Lambertian(Color),
-    Metal(Color, f64),
-    Dielectric(f64),
+/// How light interacts with a surface.
+///
+/// Only `scatter` is required, a material that doesn't emit light and whose
+/// scattering can't be evaluated for a given direction can leave the rest
+/// to the defaults.
+pub trait Material: Send + Sync {
+    /// Continue `r_in` from the hit in `record`. Returns false when the ray
+    /// is absorbed, otherwise `scattered` is the next ray and `attenuation`
+    /// what is left of the light coming back along it.
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        record: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool;
+
+    /// Light the surface gives off at the hit in `record`.
+    fn emitted(&self, _record: &HitRecord) -> Color {
+        Color::ZERO
+    }
+
+    /// Density `scatter` picks `scattered` with, per unit solid angle.
+    /// Perfectly specular materials have no density and return 0.
+    fn pdf(&self, _r_in: &Ray, _record: &HitRecord, _scattered: &Ray) -> f64 {
+        0.0
+    }
+
+    /// Color of the surface under white light, the albedo AOV.
+    fn albedo(&self) -> Color {
+        Color::ONE
+    }
+
+    /// Id for the material id AOV, 0 is left for "no material". By default
+    /// all materials of a type share one.
+    fn id(&self) -> u32 {
+        material_id(std::any::type_name::<Self>().bytes())
+    }
+}
+
+/// FNV-1a hash of `bytes` for [`Material::id`], never 0.
+pub fn material_id(bytes: impl IntoIterator<Item = u8>) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for byte in bytes {
+        hash = (hash ^ byte as u32).wrapping_mul(0x01000193);
+    }
+    hash.max(1)
+}
+
+/// Id of a built-in material, materials that look the same share it.
+fn builtin_id(kind: u8, params: &[f64]) -> u32 {
+    let bytes = params.iter().flat_map(|p| p.to_bits().to_le_bytes());
+    material_id(std::iter::once(kind).chain(bytes))
 }
 
-fn reflectance(cosine: f64, ri: f64) -> f64 {
+fn reflectance(cosine: Float, ri: Float) -> Float {
     let r0 = (1.0 - ri) / (1.0 + ri);
     let r0 = r0 * r0;
     r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
 }
 
-impl Material {
-    pub fn scatter(
+/// Ideal diffuse surface.
+pub struct Lambertian {
+    pub albedo: Color,
+}
+
+impl Lambertian {
+    pub fn new(albedo: Color) -> Self {
+        Self { albedo }
+    }
+}
+
+impl Material for Lambertian {
+    fn scatter(
+        &self,
+        _r_in: &Ray,
+        record: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        let mut scatter_direction = record.normal + vec3::random_unit_vector();
+        // catch degenerate scatter direction
+        if scatter_direction.near_zero() {
+            scatter_direction = record.normal;
+        }
+
+        *scattered = Ray::new(record.p, scatter_direction);
+        *attenuation = self.albedo;
+        true
+    }
+
+    fn pdf(&self, _r_in: &Ray, record: &HitRecord, scattered: &Ray) -> f64 {
+        // the scatter direction is cosine distributed
+        let cosine = vec3::to_f64(record.normal.dot(scattered.direction().unit_vec()));
+        cosine.max(0.0) / PI
+    }
+
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+
+    fn id(&self) -> u32 {
+        let albedo = self.albedo;
+        builtin_id(1, &[albedo.x(), albedo.y(), albedo.z(), 0.0])
+    }
+}
+
+/// Mirror, blurred by `fuzz`. See [`Conductor`] for physically based
+/// metals.
+pub struct Metal {
+    pub albedo: Color,
+    pub fuzz: Float,
+}
+
+impl Metal {
+    pub fn new(albedo: Color, fuzz: Float) -> Self {
+        Self { albedo, fuzz }
+    }
+}
+
+impl Material for Metal {
+    fn scatter(
         &self,
         r_in: &Ray,
         record: &HitRecord,
         attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool {
-        match self {
-            Material::Lambertian(albedo) => {
-                let mut scatter_direction = record.normal.add(vec3::random_unit_vector());
-                // catch degenerate scatter direction
-                if scatter_direction.near_zero() {
-                    scatter_direction = record.normal;
-                }
+        let reflected = r_in.direction().reflect(record.normal);
+        let reflected = reflected.unit_vec() + vec3::random_unit_vector() * self.fuzz;
+
+        *scattered = Ray::new(record.p, reflected);
+        *attenuation = self.albedo;
+
+        scattered.direction().dot(record.normal) > 0.0
+    }
+
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+
+    fn id(&self) -> u32 {
+        let albedo = self.albedo;
+        let fuzz = vec3::to_f64(self.fuzz);
+        builtin_id(2, &[albedo.x(), albedo.y(), albedo.z(), fuzz])
+    }
+}
+
+/// How the refraction index of a [`Dielectric`] changes with the
+/// wavelength. Both models take wavelengths in micrometers, as their
+/// coefficients are usually given.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dispersion {
+    /// `n = a + b / λ²`
+    Cauchy { a: f64, b: f64 },
+    /// `n² = 1 + Σ b λ² / (λ² - c)`
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    /// Schott N-BK7, the common crown glass.
+    pub const BK7: Self = Self::Sellmeier {
+        b: [1.03961212, 0.231792344, 1.01046945],
+        c: [0.00600069867, 0.0200179144, 103.560653],
+    };
 
-                *scattered = Ray::new(record.p, scatter_direction);
-                *attenuation = *albedo;
-                true
+    /// Refraction index at `wavelength` in nanometers.
+    pub fn refraction_index(&self, wavelength: f64) -> f64 {
+        let l2 = (wavelength / 1000.0).powi(2);
+        match *self {
+            Self::Cauchy { a, b } => a + b / l2,
+            Self::Sellmeier { b, c } => {
+                let sum: f64 = b.iter().zip(c).map(|(b, c)| b * l2 / (l2 - c)).sum();
+                (1.0 + sum).sqrt()
             }
-            Material::Metal(albedo, fuzz) => {
-                let reflected = r_in.direction().reflect(record.normal);
-                let reflected = reflected
-                    .unit_vec()
-                    .add(vec3::random_unit_vector().mul_f(*fuzz));
+        }
+    }
+}
+
+/// Glass-like material that reflects and refracts.
+///
+/// Light traveling inside is absorbed following the Beer–Lambert law. The
+/// distance is measured from where a ray enters to the back face it leaves
+/// through, so objects nested inside the medium aren't accounted for.
+///
+/// A rough surface, like frosted glass, is made of GGX microfacets that
+/// each reflect or refract. Light scattering between them is lost, which
+/// darkens very rough surfaces a little. With a strong absorption only the
+/// reflection shows, for a glossy look like a rough plastic.
+pub struct Dielectric {
+    /// used for rays without a wavelength, and for all of them when there
+    /// is no `dispersion`
+    pub refraction_index: Float,
+    /// absorption coefficient of every channel per unit of distance, zero
+    /// for clear glass
+    pub absorption: Color,
+    /// wavelength dependent refraction index for spectral rendering, which
+    /// splits white light into its colors
+    pub dispersion: Option<Dispersion>,
+    /// perceptual roughness in `0..1`, 0 is polished glass
+    pub roughness: Float,
+}
+
+impl Dielectric {
+    pub fn new(refraction_index: Float) -> Self {
+        Self {
+            refraction_index,
+            absorption: Color::ZERO,
+            dispersion: None,
+            roughness: 0.0,
+        }
+    }
 
-                *scattered = Ray::new(record.p, reflected);
-                *attenuation = *albedo;
+    /// Negative coefficients would make the glass amplify light, so they
+    /// are clamped to 0 like `NaN`.
+    pub fn with_absorption(self, absorption: Color) -> Self {
+        Self {
+            absorption: absorption.map(|a| a.max(0.0)),
+            ..self
+        }
+    }
 
-                scattered.direction().dot(record.normal) > 0.0
+    pub fn with_dispersion(self, dispersion: Dispersion) -> Self {
+        Self {
+            dispersion: Some(dispersion),
+            ..self
+        }
+    }
+
+    pub fn with_roughness(self, roughness: Float) -> Self {
+        Self { roughness, ..self }
+    }
+
+    /// Refraction index on the side `r_in` comes from over the one on the
+    /// other side of the hit in `record`.
+    fn relative_index(&self, r_in: &Ray, record: &HitRecord) -> Float {
+        let refraction_index = match (self.dispersion, r_in.wavelength()) {
+            (Some(dispersion), Some(wavelength)) => {
+                dispersion.refraction_index(wavelength) as Float
             }
-            Material::Dielectric(refraction_index) => {
-                *attenuation = [1.0, 1.0, 1.0];
-                let ri = if record.front_face {
-                    1.0 / *refraction_index
-                } else {
-                    *refraction_index
-                };
-
-                let unit_direction = r_in.direction().unit_vec();
-                let cos_theta = unit_direction.neg().dot(record.normal).min(1.0);
-                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
-
-                let cannot_refract = ri * sin_theta > 1.0;
-                let direction =
-                    if cannot_refract || reflectance(cos_theta, ri) > util::random_float() {
-                        unit_direction.reflect(record.normal)
-                    } else {
-                        unit_direction.refract(record.normal, ri)
-                    };
-
-                *scattered = Ray::new(record.p, direction);
-                true
+            _ => self.refraction_index,
+        };
+        if record.front_face {
+            1.0 / refraction_index
+        } else {
+            refraction_index
+        }
+    }
+
+    /// `scatter` off the microfacets, which reflect or refract with the
+    /// probability of their Fresnel reflectance. Returns the weight of the
+    /// scattered ray, or `None` if it is absorbed.
+    fn scatter_rough(
+        &self,
+        ggx: &Ggx,
+        r_in: &Ray,
+        record: &HitRecord,
+        scattered: &mut Ray,
+    ) -> Option<Float> {
+        let ri = self.relative_index(r_in, record);
+        let frame = Frame::new(record.normal);
+        let wo = frame.to_local(-r_in.direction().unit_vec());
+        let wm = ggx.sample_visible_normal(wo, util::random_2d());
+
+        let reflect =
+            microfacet::fresnel_dielectric(wo.dot(wm), ri) > util::random_float() as Float;
+        let wi = if reflect {
+            (-wo).reflect(wm)
+        } else {
+            (-wo).refract(wm, ri)
+        };
+        // the microfacet can send the ray to the wrong side of the surface
+        if (wi.z() > 0.0) != reflect {
+            return None;
+        }
+
+        // the Fresnel term cancels out against the choice between
+        // reflection and refraction, the rest as for `Conductor`
+        *scattered = Ray::new(record.p, frame.to_world(wi));
+        Some(ggx.g2(wo, wi) / ggx.g1(wo))
+    }
+
+    /// Absorption that leaves `color` of white light after `distance`,
+    /// which has to be positive. Channels are clamped to `(0, 1]`, a medium
+    /// can only take light away and black would need infinite absorption.
+    pub fn absorption_for(color: Color, distance: f64) -> Result<Color> {
+        if !(distance > 0.0 && distance.is_finite()) {
+            return Err(Error::InvalidMaterial(
+                "absorption distance must be positive",
+            ));
+        }
+        Ok(color.map(|c| -c.clamp(f64::MIN_POSITIVE, 1.0).ln() / distance))
+    }
+}
+
+impl Material for Dielectric {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        record: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        // a back face hit ends the way through the medium
+        *attenuation = if record.front_face {
+            Color::ONE
+        } else {
+            let distance = vec3::to_f64(record.t * r_in.direction().length());
+            (-self.absorption * distance).map(f64::exp)
+        };
+
+        let ggx = Ggx::from_roughness(self.roughness);
+        if !ggx.is_smooth() {
+            return match self.scatter_rough(&ggx, r_in, record, scattered) {
+                Some(weight) => {
+                    *attenuation *= vec3::to_f64(weight);
+                    true
+                }
+                None => false,
+            };
+        }
+
+        let ri = self.relative_index(r_in, record);
+
+        let unit_direction = r_in.direction().unit_vec();
+        let cos_theta = (-unit_direction).dot(record.normal).min(1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+        let cannot_refract = ri * sin_theta > 1.0;
+        let direction =
+            if cannot_refract || reflectance(cos_theta, ri) > util::random_float() as Float {
+                unit_direction.reflect(record.normal)
+            } else {
+                unit_direction.refract(record.normal, ri)
+            };
+
+        *scattered = Ray::new(record.p, direction);
+        true
+    }
+
+    fn pdf(&self, r_in: &Ray, record: &HitRecord, scattered: &Ray) -> f64 {
+        let ggx = Ggx::from_roughness(self.roughness);
+        if ggx.is_smooth() {
+            return 0.0;
+        }
+        let ri = self.relative_index(r_in, record);
+        let frame = Frame::new(record.normal);
+        let wo = frame.to_local(-r_in.direction().unit_vec());
+        let wi = frame.to_local(scattered.direction().unit_vec());
+
+        let pdf = if wi.z() > 0.0 {
+            let wm = (wo + wi).unit_vec();
+            let fresnel = microfacet::fresnel_dielectric(wo.dot(wm), ri);
+            ggx.visible_normal_pdf(wo, wm) * fresnel / (4.0 * wo.dot(wm))
+        } else {
+            // the microfacet normal that refracts `wo` into `wi`
+            let wm = (wo * ri + wi).unit_vec();
+            let wm = if wm.z() < 0.0 { -wm } else { wm };
+            if wo.dot(wm) <= 0.0 || wi.dot(wm) >= 0.0 {
+                return 0.0;
             }
+            let fresnel = microfacet::fresnel_dielectric(wo.dot(wm), ri);
+            let denom = wi.dot(wm) + wo.dot(wm) * ri;
+            let dwm_dwi = -wi.dot(wm) / (denom * denom);
+            ggx.visible_normal_pdf(wo, wm) * (1.0 - fresnel) * dwm_dwi
+        };
+        vec3::to_f64(pdf).max(0.0)
+    }
+
+    /// What is left of white light after one unit of distance.
+    fn albedo(&self) -> Color {
+        (-self.absorption).map(f64::exp)
+    }
+
+    fn id(&self) -> u32 {
+        let [r, g, b] = self.absorption.to_array();
+        let ri = vec3::to_f64(self.refraction_index);
+        // smooth glass leaves the roughness out
+        if self.roughness > 0.0 {
+            builtin_id(3, &[ri, r, g, b, vec3::to_f64(self.roughness)])
+        } else {
+            builtin_id(3, &[ri, r, g, b])
         }
     }
 }
+
+/// Complex refraction index `eta + i k` of a conductor, per channel.
+#[derive(Clone, Copy, Debug)]
+pub struct ComplexIor {
+    pub eta: Color,
+    pub k: Color,
+}
+
+impl ComplexIor {
+    pub const GOLD: Self = Self {
+        eta: Color::new(0.143119, 0.374957, 1.44248),
+        k: Color::new(3.98316, 2.38572, 1.60322),
+    };
+    pub const COPPER: Self = Self {
+        eta: Color::new(0.200438, 0.924033, 1.10221),
+        k: Color::new(3.91295, 2.45285, 2.14219),
+    };
+    pub const SILVER: Self = Self {
+        eta: Color::new(0.155265, 0.116723, 0.138342),
+        k: Color::new(4.82835, 3.12225, 2.14696),
+    };
+    pub const ALUMINUM: Self = Self {
+        eta: Color::new(1.65746, 0.880369, 0.521229),
+        k: Color::new(9.22387, 6.26952, 4.837),
+    };
+
+    /// Preset by its lowercase name, e.g. `"gold"`.
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "gold" => Some(Self::GOLD),
+            "copper" => Some(Self::COPPER),
+            "silver" => Some(Self::SILVER),
+            "aluminum" => Some(Self::ALUMINUM),
+            _ => None,
+        }
+    }
+}
+
+/// Metal with microfacet roughness.
+///
+/// The microfacets follow the GGX distribution with Smith masking and are
+/// sampled by their visible normals, the reflectance comes from the
+/// Fresnel equations for the complex refraction index. Light scattering
+/// between the microfacets is lost, so very rough metals get a little
+/// darker than they should.
+pub struct Conductor {
+    pub ior: ComplexIor,
+    /// perceptual roughness in `0..1`, 0 is a perfect mirror
+    pub roughness: Float,
+}
+
+impl Conductor {
+    pub fn new(ior: ComplexIor, roughness: Float) -> Self {
+        Self { ior, roughness }
+    }
+
+    fn fresnel(&self, cos_theta: Float) -> Color {
+        microfacet::fresnel_conductor(vec3::to_f64(cos_theta), self.ior.eta, self.ior.k)
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        record: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        let frame = Frame::new(record.normal);
+        let wo = frame.to_local(-r_in.direction().unit_vec());
+        if wo.z() <= 0.0 {
+            return false;
+        }
+
+        let ggx = Ggx::from_roughness(self.roughness);
+        if ggx.is_smooth() {
+            *scattered = Ray::new(
+                record.p,
+                frame.to_world(Vec3::new(-wo.x(), -wo.y(), wo.z())),
+            );
+            *attenuation = self.fresnel(wo.z());
+            return true;
+        }
+
+        let wm = ggx.sample_visible_normal(wo, util::random_2d());
+        let wi = (-wo).reflect(wm);
+        if wi.z() <= 0.0 {
+            return false;
+        }
+
+        // the distribution and the cosine cancel out against the density
+        // of the visible normals, leaving the masking of `wi`
+        *scattered = Ray::new(record.p, frame.to_world(wi));
+        *attenuation = self.fresnel(wo.dot(wm)) * vec3::to_f64(ggx.g2(wo, wi) / ggx.g1(wo));
+        true
+    }
+
+    fn pdf(&self, r_in: &Ray, record: &HitRecord, scattered: &Ray) -> f64 {
+        let ggx = Ggx::from_roughness(self.roughness);
+        if ggx.is_smooth() {
+            return 0.0;
+        }
+        let frame = Frame::new(record.normal);
+        let wo = frame.to_local(-r_in.direction().unit_vec());
+        let wi = frame.to_local(scattered.direction().unit_vec());
+        // `scatter` absorbs the rays that end up below the surface
+        if wi.z() <= 0.0 {
+            return 0.0;
+        }
+        let wm = (wo + wi).unit_vec();
+        let pdf = ggx.visible_normal_pdf(wo, wm) / (4.0 * wo.dot(wm));
+        vec3::to_f64(pdf).max(0.0)
+    }
+
+    /// Reflectance at normal incidence.
+    fn albedo(&self) -> Color {
+        self.fresnel(1.0)
+    }
+
+    fn id(&self) -> u32 {
+        let ComplexIor { eta, k } = self.ior;
+        let [er, eg, eb] = eta.to_array();
+        let [kr, kg, kb] = k.to_array();
+        let roughness = vec3::to_f64(self.roughness);
+        builtin_id(4, &[er, eg, eb, kr, kg, kb, roughness])
+    }
+}
@@ -0,0 +1,173 @@
+use std::f64::consts::PI;
+
+use crate::{
+    color::Color,
+    vec3::{Float, Vec3},
+};
+
+/// Orthonormal basis around a surface normal, the normal is `z` of the
+/// local coordinates.
+#[derive(Clone, Copy)]
+pub struct Frame {
+    s: Vec3,
+    t: Vec3,
+    n: Vec3,
+}
+
+impl Frame {
+    /// Frame around the unit vector `n`, after Duff et al., "Building an
+    /// Orthonormal Basis, Revisited".
+    pub fn new(n: Vec3) -> Self {
+        let sign = (1.0 as Float).copysign(n.z());
+        let a = -1.0 / (sign + n.z());
+        let b = n.x() * n.y() * a;
+        Self {
+            s: Vec3::new(1.0 + sign * n.x() * n.x() * a, sign * b, -sign * n.x()),
+            t: Vec3::new(b, sign + n.y() * n.y() * a, -n.y()),
+            n,
+        }
+    }
+
+    pub fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(self.s), v.dot(self.t), v.dot(self.n))
+    }
+
+    pub fn to_world(&self, v: Vec3) -> Vec3 {
+        self.s * v.x() + self.t * v.y() + self.n * v.z()
+    }
+}
+
+/// The GGX (Trowbridge-Reitz) distribution of microfacet normals with
+/// Smith masking, isotropic. Directions are in the local coordinates of a
+/// [`Frame`] and point away from the surface.
+#[derive(Clone, Copy, Debug)]
+pub struct Ggx {
+    pub alpha: Float,
+}
+
+impl Ggx {
+    /// Distribution for a perceptual `roughness` in `0..1`, which is
+    /// squared to get the width of the distribution.
+    pub fn from_roughness(roughness: Float) -> Self {
+        Self {
+            alpha: (roughness * roughness).max(1e-4),
+        }
+    }
+
+    /// Below this width the surface is treated as perfectly smooth, the
+    /// distribution gets too peaked to evaluate reliably.
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < 1e-3
+    }
+
+    /// Density of microfacets with normal `wm`.
+    pub fn d(&self, wm: Vec3) -> Float {
+        let cos2 = wm.z() * wm.z();
+        if cos2 <= 0.0 {
+            return 0.0;
+        }
+        let a2 = self.alpha * self.alpha;
+        let denom = cos2 * (a2 - 1.0) + 1.0;
+        a2 / (PI as Float * denom * denom)
+    }
+
+    fn lambda(&self, w: Vec3) -> Float {
+        let cos2 = w.z() * w.z();
+        if cos2 <= 0.0 {
+            return 0.0;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0) / 2.0
+    }
+
+    /// Fraction of the microfacets seen from `w` that aren't masked.
+    pub fn g1(&self, w: Vec3) -> Float {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Fraction of the microfacets seen from both `wo` and `wi`.
+    pub fn g2(&self, wo: Vec3, wi: Vec3) -> Float {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Microfacet normal visible from `wo` for the samples `u` in `0..1`,
+    /// after Heitz, "Sampling the GGX Distribution of Visible Normals".
+    pub fn sample_visible_normal(&self, wo: Vec3, (u1, u2): (f64, f64)) -> Vec3 {
+        let alpha = self.alpha;
+        // stretch the view direction so the distribution becomes a
+        // hemisphere
+        let vh = Vec3::new(alpha * wo.x(), alpha * wo.y(), wo.z()).unit_vec();
+        let len_sq = vh.x() * vh.x() + vh.y() * vh.y();
+        let t1 = if len_sq > 0.0 {
+            Vec3::new(-vh.y(), vh.x(), 0.0) / len_sq.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(t1);
+
+        // a point on the disk, squeezed to the part of the hemisphere that
+        // is visible
+        let r = u1.sqrt() as Float;
+        let phi = (2.0 * PI * u2) as Float;
+        let (p1, p2) = (r * phi.cos(), r * phi.sin());
+        let s = 0.5 * (1.0 + vh.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * p2;
+        let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+        // and unstretch the normal
+        Vec3::new(alpha * nh.x(), alpha * nh.y(), nh.z().max(0.0)).unit_vec()
+    }
+
+    /// Density of `sample_visible_normal` picking `wm`, per unit solid
+    /// angle of normals.
+    pub fn visible_normal_pdf(&self, wo: Vec3, wm: Vec3) -> Float {
+        if wo.z() <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * wo.dot(wm).max(0.0) * self.d(wm) / wo.z()
+    }
+}
+
+/// Fresnel reflectance of a dielectric for unpolarized light at
+/// `cos_theta`, `ri` is the refraction index on the side the light comes
+/// from over the one on the other side.
+pub fn fresnel_dielectric(cos_theta: Float, ri: Float) -> Float {
+    let cos_i = cos_theta.clamp(0.0, 1.0);
+    let sin2_t = ri * ri * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        // total internal reflection
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (ri * cos_i - cos_t) / (ri * cos_i + cos_t);
+    let rp = (cos_i - ri * cos_t) / (cos_i + ri * cos_t);
+    (rs * rs + rp * rp) / 2.0
+}
+
+/// Fresnel reflectance of a conductor with the complex refraction index
+/// `eta + i k` per channel, for unpolarized light at `cos_theta`.
+pub fn fresnel_conductor(cos_theta: f64, eta: Color, k: Color) -> Color {
+    let cos_theta = cos_theta.clamp(0.0, 1.0);
+    let cos2 = cos_theta * cos_theta;
+    let sin2 = 1.0 - cos2;
+    let channel = |eta: f64, k: f64| {
+        let (eta2, k2) = (eta * eta, k * k);
+        let t0 = eta2 - k2 - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+
+        let t1 = a2_plus_b2 + cos2;
+        let t2 = 2.0 * cos_theta * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rs + rp)
+    };
+    let [eta, k] = [eta.to_array(), k.to_array()];
+    Color::new(
+        channel(eta[0], k[0]),
+        channel(eta[1], k[1]),
+        channel(eta[2], k[2]),
+    )
+}
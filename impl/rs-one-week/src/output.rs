@@ -0,0 +1,191 @@
+use std::{
+    fs,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use exr::prelude::{
+    AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, SmallVec,
+    WritableImage, f16,
+};
+
+use crate::{color, error::Result, framebuffer::FrameBuffer, tonemap::ToneMapping};
+
+/// Sample type used for the channels of an OpenEXR file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ExrPrecision {
+    #[default]
+    Half,
+    Float,
+}
+
+/// How a frame buffer is turned into image files.
+#[derive(Clone, Debug, Default)]
+pub struct OutputOptions {
+    pub exr_precision: ExrPrecision,
+    /// only used for 8-bit formats, float formats keep the scene radiance
+    pub tone_mapping: ToneMapping,
+    /// where to write the per-pixel sample count heat map, if anywhere
+    pub sample_heatmap: Option<String>,
+}
+
+/// Samples of one OpenEXR channel.
+pub enum ExrSamples {
+    /// stored as half or full float depending on [`ExrPrecision`]
+    Float(Vec<f32>),
+    /// stored as-is, for ids that must not be rounded
+    Uint(Vec<u32>),
+}
+
+/// A named group of channels, stored as one layer of an OpenEXR file.
+///
+/// The unnamed layer is the main image; named layers get their name
+/// prefixed to each channel, e.g. `albedo.R`.
+pub struct ExrLayer {
+    pub name: Option<String>,
+    pub channels: Vec<(String, ExrSamples)>,
+}
+
+impl ExrLayer {
+    /// the R, G and B channels of a frame buffer
+    pub fn rgb(name: Option<&str>, fb: &FrameBuffer) -> Self {
+        Self {
+            name: name.map(str::to_owned),
+            channels: vec![
+                ("R".to_owned(), ExrSamples::Float(fb.channel(0))),
+                ("G".to_owned(), ExrSamples::Float(fb.channel(1))),
+                ("B".to_owned(), ExrSamples::Float(fb.channel(2))),
+            ],
+        }
+    }
+}
+
+/// Write the frame buffer to `path`, picking the format from the extension.
+///
+/// `.exr` and `.hdr` keep the full linear radiance, everything else is
+/// tone mapped and quantized to 8 bits.
+///
+/// `extra_layers` (AOVs) are stored next to the image in OpenEXR files, for
+/// any other format each one goes to its own `<stem>.<layer>.exr` file.
+/// Returns the paths of those extra files.
+pub fn save(
+    path: &str,
+    fb: &FrameBuffer,
+    extra_layers: Vec<ExrLayer>,
+    options: &OutputOptions,
+) -> Result<Vec<PathBuf>> {
+    let ext = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+
+    if ext.as_deref() == Some("exr") {
+        let mut layers = vec![ExrLayer::rgb(None, fb)];
+        layers.extend(extra_layers);
+        write_exr(
+            path,
+            fb.width(),
+            fb.height(),
+            &layers,
+            options.exr_precision,
+        )?;
+        return Ok(Vec::new());
+    }
+
+    let mut layer_paths = Vec::new();
+    for mut layer in extra_layers {
+        let name = layer.name.take().unwrap_or_default();
+        let layer_path = Path::new(path).with_extension(format!("{name}.exr"));
+        write_exr(
+            &layer_path.to_string_lossy(),
+            fb.width(),
+            fb.height(),
+            &[layer],
+            options.exr_precision,
+        )?;
+        layer_paths.push(layer_path);
+    }
+
+    match ext.as_deref() {
+        Some("hdr") => write_hdr(path, fb)?,
+        Some("ppm") => write_ppm(path, fb, &options.tone_mapping)?,
+        _ => to_rgb8(fb, &options.tone_mapping).save(path)?,
+    }
+    Ok(layer_paths)
+}
+
+/// Tone map and quantize the frame buffer to an 8-bit image.
+pub fn to_rgb8(fb: &FrameBuffer, tone_mapping: &ToneMapping) -> image::RgbImage {
+    image::RgbImage::from_raw(
+        fb.width() as u32,
+        fb.height() as u32,
+        fb.to_rgb8(tone_mapping),
+    )
+    .expect("Frame buffer size doesn't match its pixels")
+}
+
+/// Write a scanline OpenEXR file.
+///
+/// All layers go into a single part, told apart by the `layer.` prefix of
+/// their channel names, which is how most compositors expect AOVs.
+pub fn write_exr(
+    path: &str,
+    width: usize,
+    height: usize,
+    layers: &[ExrLayer],
+    precision: ExrPrecision,
+) -> exr::error::Result<()> {
+    let channels = layers
+        .iter()
+        .flat_map(|layer| {
+            layer.channels.iter().map(move |(name, samples)| {
+                let name = match &layer.name {
+                    Some(layer) => format!("{layer}.{name}"),
+                    None => name.clone(),
+                };
+                let samples = match (samples, precision) {
+                    (ExrSamples::Float(s), ExrPrecision::Half) => {
+                        FlatSamples::F16(s.iter().copied().map(f16::from_f32).collect())
+                    }
+                    (ExrSamples::Float(s), ExrPrecision::Float) => FlatSamples::F32(s.clone()),
+                    (ExrSamples::Uint(s), _) => FlatSamples::U32(s.clone()),
+                };
+                AnyChannel::new(name.as_str(), samples)
+            })
+        })
+        .collect::<SmallVec<_>>();
+
+    let layer = Layer::new(
+        (width, height),
+        LayerAttributes::default(),
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(channels),
+    );
+    Image::from_layer(layer).write().to_file(path)
+}
+
+/// Write a Radiance RGBE (`.hdr`) file.
+pub fn write_hdr(path: &str, fb: &FrameBuffer) -> image::ImageResult<()> {
+    let pixels = fb
+        .pixels()
+        .iter()
+        .map(|p| image::Rgb(p.to_array().map(|c| c as f32)))
+        .collect::<Vec<_>>();
+
+    let file = BufWriter::new(fs::File::create(path)?);
+    image::codecs::hdr::HdrEncoder::new(file).encode(&pixels, fb.width(), fb.height())
+}
+
+/// Write a plain-text (P3) ppm file.
+pub fn write_ppm(path: &str, fb: &FrameBuffer, tone_mapping: &ToneMapping) -> io::Result<()> {
+    let mut file = BufWriter::new(fs::File::create(path)?);
+
+    // ppm header
+    write!(file, "P3\n{} {}\n255\n", fb.width(), fb.height())?;
+
+    for pixel in fb.pixels() {
+        let [r, g, b] = color::get_pixel(*pixel, tone_mapping);
+        writeln!(file, "{r} {g} {b}")?;
+    }
+    file.flush()
+}
@@ -0,0 +1,143 @@
+use std::{
+    fmt,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// What a running render reports to its [`ProgressCallback`].
+#[derive(Clone, Debug)]
+pub enum ProgressEvent {
+    /// a render of `width` x `height` pixels starts, continuing the
+    /// checkpoint at `resumed` if there is one
+    Started {
+        width: usize,
+        height: usize,
+        resumed: Option<String>,
+    },
+    /// tiles finished so far, counting every pass, sent at most every
+    /// 250 ms and for the last tile
+    Tiles {
+        done: usize,
+        total: usize,
+        rays_per_sec: f64,
+        eta: Duration,
+    },
+    /// a progressive pass brought every pixel to `samples_per_pixel`
+    PassDone {
+        pass: u32,
+        passes: u32,
+        samples_per_pixel: u32,
+        elapsed: Duration,
+    },
+    /// a periodic checkpoint couldn't be written, the render goes on
+    CheckpointFailed { path: String, error: String },
+    /// the final checkpoint is about to be written
+    WritingCheckpoint { path: String },
+    /// the render is over, `stopped` if it was cancelled or ran out of
+    /// time before every sample was taken. A cancelled render can leave
+    /// `unsampled_pixels` black
+    Finished {
+        elapsed: Duration,
+        rays: u64,
+        stopped: bool,
+        average_samples: f64,
+        unsampled_pixels: usize,
+    },
+    /// a coordinator waits for workers on `addr`
+    Listening { addr: String },
+    /// a worker connection ended early, its tile goes to another worker
+    WorkerDropped { peer: String, error: String },
+}
+
+/// Receives the [`ProgressEvent`]s of a render, from whichever thread
+/// sends them.
+#[derive(Clone)]
+pub struct ProgressCallback(Arc<dyn Fn(&ProgressEvent) + Send + Sync>);
+
+impl ProgressCallback {
+    pub fn new(f: impl Fn(&ProgressEvent) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(f))
+    }
+
+    pub fn report(&self, event: &ProgressEvent) {
+        (self.0)(event);
+    }
+}
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
+
+/// Send `event` to `callback`, if there is one.
+pub(crate) fn report(callback: Option<&ProgressCallback>, event: impl FnOnce() -> ProgressEvent) {
+    if let Some(callback) = callback {
+        callback.report(&event());
+    }
+}
+
+/// Counts completed tiles and traced rays while a render is running and
+/// reports them with the estimated time remaining.
+pub(crate) struct Progress<'a> {
+    callback: Option<&'a ProgressCallback>,
+    total_tiles: usize,
+    done_tiles: AtomicUsize,
+    rays: AtomicU64,
+    start: Instant,
+    last_report: Mutex<Instant>,
+}
+
+impl<'a> Progress<'a> {
+    pub fn new(total_tiles: usize, callback: Option<&'a ProgressCallback>) -> Self {
+        let now = Instant::now();
+        Self {
+            callback,
+            total_tiles,
+            done_tiles: AtomicUsize::new(0),
+            rays: AtomicU64::new(0),
+            start: now,
+            last_report: Mutex::new(now),
+        }
+    }
+
+    /// Record a finished tile and the number of rays it traced.
+    pub fn tile_done(&self, rays: u64) {
+        self.rays.fetch_add(rays, Ordering::Relaxed);
+        let done = self.done_tiles.fetch_add(1, Ordering::Relaxed) + 1;
+        let Some(callback) = self.callback else {
+            return;
+        };
+
+        // don't flood the receiver, but always report the last tile
+        let mut last_report = self.last_report.lock().unwrap();
+        if done == self.total_tiles || last_report.elapsed() >= Duration::from_millis(250) {
+            *last_report = Instant::now();
+            callback.report(&self.tiles_event(done));
+        }
+    }
+
+    pub fn rays(&self) -> u64 {
+        self.rays.load(Ordering::Relaxed)
+    }
+
+    fn tiles_event(&self, done: usize) -> ProgressEvent {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let fraction = done as f64 / self.total_tiles.max(1) as f64;
+        let eta = if done > 0 {
+            elapsed * (1.0 - fraction) / fraction
+        } else {
+            0.0
+        };
+
+        ProgressEvent::Tiles {
+            done,
+            total: self.total_tiles,
+            rays_per_sec: self.rays() as f64 / elapsed.max(1e-9),
+            eta: Duration::from_secs_f64(eta.max(0.0)),
+        }
+    }
+}
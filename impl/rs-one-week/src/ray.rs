@@ -1,20 +1,33 @@
-use crate::vec3::{self, Point3, SliceOp, Vec3};
+use crate::vec3::{Float, Point3, Vec3};
 
+#[derive(Clone, Copy)]
 pub struct Ray {
     origin: Point3,
     direction: Vec3,
+    /// in nanometers, set when rendering spectrally
+    wavelength: Option<f64>,
 }
 
 impl Ray {
     pub fn init() -> Self {
         Self {
-            origin: vec3::init(),
-            direction: vec3::init(),
+            origin: Vec3::ZERO,
+            direction: Vec3::ZERO,
+            wavelength: None,
         }
     }
 
     pub fn new(origin: Point3, direction: Vec3) -> Self {
-        Self { origin, direction }
+        Self {
+            origin,
+            direction,
+            wavelength: None,
+        }
+    }
+
+    /// The same ray carrying light of a single wavelength.
+    pub fn with_wavelength(self, wavelength: Option<f64>) -> Self {
+        Self { wavelength, ..self }
     }
 
     pub fn origin(&self) -> &Point3 {
@@ -23,8 +36,45 @@ impl Ray {
     pub fn direction(&self) -> &Vec3 {
         &self.direction
     }
+    pub fn wavelength(&self) -> Option<f64> {
+        self.wavelength
+    }
+
+    pub fn at(&self, t: Float) -> Point3 {
+        self.origin + self.direction * t
+    }
+}
+
+/// One [`Float`] per ray of a [`RayPacket`].
+#[cfg(not(feature = "f32"))]
+pub type Lanes = wide::f64x4;
+#[cfg(feature = "f32")]
+pub type Lanes = wide::f32x8;
 
-    pub fn at(&self, t: f64) -> Point3 {
-        self.origin.add(self.direction.mul_f(t))
+/// Number of rays in a [`RayPacket`], as many as fit into a SIMD register.
+#[cfg(not(feature = "f32"))]
+pub const LANES: usize = 4;
+#[cfg(feature = "f32")]
+pub const LANES: usize = 8;
+
+/// Rays traced together, one per SIMD lane.
+///
+/// The components are stored per axis, so that intersection tests run on
+/// all lanes at once. The rays themselves are kept as well for the work
+/// that is done per lane once something is hit.
+pub struct RayPacket {
+    pub rays: [Ray; LANES],
+    pub origin: [Lanes; 3],
+    pub direction: [Lanes; 3],
+}
+
+impl RayPacket {
+    pub fn new(rays: [Ray; LANES]) -> Self {
+        let axis = |v: fn(&Ray) -> &Vec3, i: usize| Lanes::new(rays.map(|r| v(&r)[i]));
+        Self {
+            rays,
+            origin: [0, 1, 2].map(|i| axis(Ray::origin, i)),
+            direction: [0, 1, 2].map(|i| axis(Ray::direction, i)),
+        }
     }
 }
@@ -0,0 +1,293 @@
+use std::{cell::RefCell, str::FromStr};
+
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+
+use crate::util;
+
+/// Sequence the sample dimensions of a pixel are drawn from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SamplerKind {
+    /// independent uniform random numbers
+    #[default]
+    Random,
+    /// jittered grid, strata shuffled independently for each dimension
+    Stratified,
+    /// Halton sequence, Owen scrambled per pixel
+    Halton,
+    /// Owen-scrambled Sobol points, padded over dimension pairs
+    Sobol,
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "random" | "independent" => Ok(Self::Random),
+            "stratified" => Ok(Self::Stratified),
+            "halton" => Ok(Self::Halton),
+            "sobol" => Ok(Self::Sobol),
+            _ => Err(format!("Unknown sampler: {s}")),
+        }
+    }
+}
+
+/// Describes how the samples of every pixel of a render are generated.
+///
+/// `start` binds the calling thread to one pixel sample, after which
+/// `get_1d` / `get_2d` (and everything built on `util::random_float`)
+/// walk through its dimensions: pixel position, lens position and then
+/// one or two dimensions per scattering event.
+#[derive(Clone, Copy, Debug)]
+pub struct Sampler {
+    pub kind: SamplerKind,
+    pub seed: u64,
+    pub samples_per_pixel: u64,
+}
+
+#[derive(Clone)]
+struct SampleState {
+    sampler: Sampler,
+    pixel: u64,
+    index: u64,
+    dimension: u32,
+    rng: Pcg32,
+}
+
+thread_local! {
+    static STATE: RefCell<SampleState> = RefCell::new(SampleState {
+        sampler: Sampler {
+            kind: SamplerKind::Random,
+            seed: 0,
+            samples_per_pixel: 1,
+        },
+        pixel: 0,
+        index: 0,
+        dimension: 0,
+        rng: Pcg32::seed_from_u64(0),
+    });
+}
+
+/// first primes, one per Halton dimension
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+impl Sampler {
+    /// Start sample `index` of `pixel` on the calling thread.
+    ///
+    /// The pixel index picks the PCG stream and the sample number its
+    /// starting state, so the same `(seed, pixel, index)` always produces
+    /// the same path no matter which thread traces it.
+    pub fn start(&self, pixel: u64, index: u64) {
+        let state = util::mix64(self.seed ^ util::mix64(index));
+        STATE.with_borrow_mut(|s| {
+            s.sampler = *self;
+            s.pixel = pixel;
+            s.index = index;
+            s.dimension = 0;
+            s.rng = Pcg32::new(state, pixel);
+        });
+    }
+}
+
+/// Restart this thread's generator from `seed` with plain random numbers,
+/// for work outside of a pixel sample such as building a scene.
+pub fn seed(seed: u64) {
+    STATE.with_borrow_mut(|s| {
+        s.sampler = Sampler {
+            kind: SamplerKind::Random,
+            seed,
+            samples_per_pixel: 1,
+        };
+        s.rng = Pcg32::seed_from_u64(seed);
+    });
+}
+
+/// Where the calling thread is in its current sample, see [`save`].
+pub struct SampleCursor(SampleState);
+
+/// Remember the calling thread's position in its current sample, so that
+/// several samples can be interleaved on one thread.
+pub fn save() -> SampleCursor {
+    SampleCursor(STATE.with_borrow(|s| s.clone()))
+}
+
+/// Continue the sample `cursor` was saved from on the calling thread.
+pub fn restore(cursor: &SampleCursor) {
+    STATE.with_borrow_mut(|s| *s = cursor.0.clone());
+}
+
+/// Next dimension of the current sample, in `[0, 1)`.
+pub fn get_1d() -> f64 {
+    STATE.with_borrow_mut(|s| {
+        let dim = s.dimension;
+        s.dimension += 1;
+
+        match s.sampler.kind {
+            SamplerKind::Random => s.rng.random_range(0.0..1.0),
+            SamplerKind::Stratified => {
+                let n = s.sampler.samples_per_pixel.max(1) as u32;
+                let stratum = permute(s.stratum_index(n), n, s.hash(dim));
+                (stratum as f64 + s.rng.random_range(0.0..1.0)) / n as f64
+            }
+            SamplerKind::Halton => s.halton(dim),
+            SamplerKind::Sobol => {
+                let seed = s.hash(dim);
+                let index = nested_uniform_scramble(s.index as u32, seed);
+                to_unit(nested_uniform_scramble(index.reverse_bits(), mix32(seed)))
+            }
+        }
+    })
+}
+
+/// Next two dimensions of the current sample, in `[0, 1)²`.
+pub fn get_2d() -> (f64, f64) {
+    STATE.with_borrow_mut(|s| {
+        let dim = s.dimension;
+        s.dimension += 2;
+
+        match s.sampler.kind {
+            SamplerKind::Random => (s.rng.random_range(0.0..1.0), s.rng.random_range(0.0..1.0)),
+            SamplerKind::Stratified => {
+                // as close to a square grid as the sample count allows
+                let spp = s.sampler.samples_per_pixel.max(1) as u32;
+                let nx = (spp as f64).sqrt().round().max(1.0) as u32;
+                let ny = spp.div_ceil(nx);
+
+                let cell = permute(s.stratum_index(nx * ny), nx * ny, s.hash(dim));
+                let (cx, cy) = (cell % nx, cell / nx);
+                (
+                    (cx as f64 + s.rng.random_range(0.0..1.0)) / nx as f64,
+                    (cy as f64 + s.rng.random_range(0.0..1.0)) / ny as f64,
+                )
+            }
+            SamplerKind::Halton => (s.halton(dim), s.halton(dim + 1)),
+            SamplerKind::Sobol => {
+                let seed = s.hash(dim);
+                let index = nested_uniform_scramble(s.index as u32, seed);
+                let x = index.reverse_bits();
+                let y = sobol_dim1(index);
+                (
+                    to_unit(nested_uniform_scramble(x, mix32(seed))),
+                    to_unit(nested_uniform_scramble(y, mix32(seed ^ 1))),
+                )
+            }
+        }
+    })
+}
+
+impl SampleState {
+    /// hash unique to this pixel and dimension, shared by all its samples
+    fn hash(&self, dim: u32) -> u32 {
+        let h = util::mix64(self.sampler.seed ^ util::mix64(self.pixel ^ ((dim as u64) << 40)));
+        (h >> 32) as u32
+    }
+
+    fn stratum_index(&self, strata: u32) -> u32 {
+        (self.index % strata as u64) as u32
+    }
+
+    fn halton(&mut self, dim: u32) -> f64 {
+        let Some(&base) = PRIMES.get(dim as usize) else {
+            // out of primes, the remaining dimensions are plain random
+            return self.rng.random_range(0.0..1.0);
+        };
+
+        owen_scrambled_radical_inverse(base, self.index, self.hash(dim))
+    }
+}
+
+fn to_unit(x: u32) -> f64 {
+    x as f64 / 4294967296.0
+}
+
+fn mix32(x: u32) -> u32 {
+    (util::mix64(x as u64) >> 32) as u32
+}
+
+/// Radical inverse of `index` where every digit is permuted based on the
+/// digits before it, see pbrt-v4 `OwenScrambledRadicalInverse`.
+fn owen_scrambled_radical_inverse(base: u64, mut index: u64, hash: u32) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_n = 1.0;
+    let mut reversed: u64 = 0;
+
+    // keep going past the last digit of index until precision runs out,
+    // leading zeros get scrambled too
+    while 1.0 - inv_base_n < 1.0 {
+        let next = index / base;
+        let digit = index - next * base;
+        let digit_hash = (util::mix64(hash as u64 ^ reversed) >> 32) as u32;
+        let digit = permute(digit as u32, base as u32, digit_hash) as u64;
+        reversed = reversed * base + digit;
+        inv_base_n *= inv_base;
+        index = next;
+    }
+    (reversed as f64 * inv_base_n).min(1.0 - f64::EPSILON)
+}
+
+/// Second Sobol dimension, its generator matrix is the binary Pascal matrix.
+fn sobol_dim1(index: u32) -> u32 {
+    let mut v = 1 << 31;
+    let mut result = 0;
+    let mut index = index;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+/// Owen scrambling of the bits of `x`, see Burley, "Practical Hash-based
+/// Owen Scrambling" (JCGT 2020).
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+/// Random permutation of `i` in `0..len` without a table, see Kensler,
+/// "Correlated Multi-Jittered Sampling" (Pixar 2013).
+fn permute(mut i: u32, len: u32, seed: u32) -> u32 {
+    let mut w = len.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            break;
+        }
+    }
+    i.wrapping_add(seed) % len
+}
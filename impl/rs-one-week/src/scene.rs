@@ -0,0 +1,368 @@
+use std::{fmt, sync::Arc};
+
+use crate::{
+    camera::Camera,
+    color::Color,
+    error,
+    hittable::HittableList,
+    material::{ComplexIor, Conductor, Dielectric, Dispersion, Lambertian, Material, Metal},
+    sphere::Sphere,
+    util,
+    vec3::{self, Float, Point3, Vec3},
+};
+
+/// Everything `Camera::new` takes.
+#[derive(Clone, Debug)]
+pub struct CameraSettings {
+    pub aspect_ratio: f64,
+    pub image_width: i32,
+    pub samples_per_pixel: i32,
+    pub max_depth: i32,
+    pub vfov: Float,
+    pub look_from: Point3,
+    pub look_at: Point3,
+    pub vup: Vec3,
+    pub defocus_angle: Float,
+    pub focus_dist: Float,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            aspect_ratio: 16.0 / 9.0,
+            image_width: 1920,
+            samples_per_pixel: 64,
+            max_depth: 16,
+            vfov: 24.0,
+            look_from: Vec3::new(13.0, 2.0, 5.0),
+            look_at: Vec3::ZERO,
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            defocus_angle: 0.6,
+            focus_dist: 10.0,
+        }
+    }
+}
+
+impl CameraSettings {
+    pub fn camera(&self) -> error::Result<Camera> {
+        Camera::new(
+            self.aspect_ratio,
+            self.image_width,
+            self.samples_per_pixel,
+            self.max_depth,
+            self.vfov,
+            self.look_from,
+            self.look_at,
+            self.vup,
+            self.defocus_angle,
+            self.focus_dist,
+        )
+    }
+}
+
+/// Camera, materials and spheres of a scene.
+///
+/// Scene files are plain text with one statement per line, `#` starts a
+/// comment:
+///
+/// ```text
+/// image <width> <aspect ratio, e.g. 1.5 or 16:9>
+/// samples <per pixel>
+/// max_depth <bounces>
+/// camera [look_from x y z] [look_at x y z] [vup x y z] [vfov deg]
+///        [defocus_angle deg] [focus_dist d]
+/// material <name> lambertian <r g b>
+/// material <name> metal <r g b> <fuzz>
+/// material <name> conductor <gold | copper | silver | aluminum> <roughness>
+/// material <name> conductor <eta r g b> <k r g b> <roughness>
+/// material <name> dielectric <ior> [absorption <r g b>] [roughness <r>]
+///          [absorption_color <r g b> <distance>]
+///          [cauchy <a> <b> | sellmeier <b1 b2 b3> <c1 c2 c3> | bk7]
+/// sphere <x y z> <radius> <material name>
+/// random_spheres <seed>
+/// ```
+///
+/// `random_spheres` scatters the small spheres of the book cover.
+/// `absorption_color` is an easier way to set the absorption, white light
+/// turns into that color after going through `distance` of glass. The
+/// dispersion of a dielectric only shows when rendering spectrally, its
+/// coefficients take wavelengths in micrometers.
+pub struct Scene {
+    pub camera: CameraSettings,
+    materials: Vec<Arc<dyn Material>>,
+    spheres: Vec<(Point3, Float, usize)>,
+}
+
+#[derive(Debug)]
+pub struct SceneError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl Scene {
+    pub fn new(camera: CameraSettings) -> Self {
+        Self {
+            camera,
+            materials: Vec::new(),
+            spheres: Vec::new(),
+        }
+    }
+
+    /// The book cover, the random spheres are laid out from `seed`.
+    pub fn cover(seed: u64) -> Self {
+        let mut scene = Self::new(CameraSettings::default());
+
+        let ground = scene.add_material(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        scene.add_sphere(Vec3::new(0.0, -1000.0, 0.0), 1000.0, ground);
+
+        scene.add_random_spheres(seed);
+
+        let dielectric = scene.add_material(Dielectric::new(1.5));
+        let lambertian = scene.add_material(Lambertian::new(Color::new(0.4, 0.2, 0.1)));
+        let metal = scene.add_material(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
+        scene.add_sphere(Vec3::new(0.0, 1.0, 0.0), 1.0, dielectric);
+        scene.add_sphere(Vec3::new(-4.0, 1.0, 0.0), 1.0, lambertian);
+        scene.add_sphere(Vec3::new(4.0, 1.0, 0.0), 1.0, metal);
+        scene
+    }
+
+    pub fn parse(source: &str) -> Result<Self, SceneError> {
+        let mut scene = Self::new(CameraSettings::default());
+        let mut names: Vec<(String, usize)> = Vec::new();
+
+        for (n, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut tokens = Tokens {
+                line: n + 1,
+                iter: line.split_whitespace(),
+            };
+            let Some(keyword) = tokens.iter.next() else {
+                continue;
+            };
+
+            match keyword {
+                "image" => {
+                    scene.camera.image_width = tokens.parse("image width")?;
+                    scene.camera.aspect_ratio = tokens.aspect_ratio()?;
+                }
+                "samples" => scene.camera.samples_per_pixel = tokens.parse("sample count")?,
+                "max_depth" => scene.camera.max_depth = tokens.parse("depth")?,
+                "camera" => {
+                    while let Some(key) = tokens.iter.next() {
+                        let camera = &mut scene.camera;
+                        match key {
+                            "look_from" => camera.look_from = tokens.vec3()?,
+                            "look_at" => camera.look_at = tokens.vec3()?,
+                            "vup" => camera.vup = tokens.vec3()?,
+                            "vfov" => camera.vfov = tokens.parse("field of view")?,
+                            "defocus_angle" => camera.defocus_angle = tokens.parse("angle")?,
+                            "focus_dist" => camera.focus_dist = tokens.parse("distance")?,
+                            _ => return Err(tokens.error(format!("unknown camera key {key}"))),
+                        }
+                    }
+                }
+                "material" => {
+                    let name = tokens.word("material name")?;
+                    let index = match tokens.word("material type")? {
+                        "lambertian" => scene.add_material(Lambertian::new(tokens.color()?)),
+                        "metal" => {
+                            let albedo = tokens.color()?;
+                            scene.add_material(Metal::new(albedo, tokens.parse("fuzz")?))
+                        }
+                        "conductor" => {
+                            let ior = match tokens.iter.clone().next() {
+                                Some(name) if name.parse::<f64>().is_err() => {
+                                    tokens.iter.next();
+                                    ComplexIor::preset(name).ok_or_else(|| {
+                                        tokens.error(format!("unknown conductor {name}"))
+                                    })?
+                                }
+                                _ => ComplexIor {
+                                    eta: tokens.color()?,
+                                    k: tokens.color()?,
+                                },
+                            };
+                            scene.add_material(Conductor::new(ior, tokens.parse("roughness")?))
+                        }
+                        "dielectric" => {
+                            let mut dielectric = Dielectric::new(tokens.parse("refraction index")?);
+                            while let Some(key) = tokens.iter.next() {
+                                dielectric = match key {
+                                    "absorption" => {
+                                        let absorption = tokens.color()?;
+                                        if absorption.to_array().iter().any(|&a| a < 0.0) {
+                                            return Err(tokens
+                                                .error("absorption can't be negative".to_owned()));
+                                        }
+                                        dielectric.with_absorption(absorption)
+                                    }
+                                    "absorption_color" => {
+                                        let color = tokens.color()?;
+                                        let distance = tokens.parse("distance")?;
+                                        let absorption =
+                                            Dielectric::absorption_for(color, distance)
+                                                .map_err(|e| tokens.error(e.to_string()))?;
+                                        dielectric.with_absorption(absorption)
+                                    }
+                                    "roughness" => {
+                                        dielectric.with_roughness(tokens.parse("roughness")?)
+                                    }
+                                    "cauchy" => dielectric.with_dispersion(Dispersion::Cauchy {
+                                        a: tokens.parse("cauchy coefficient")?,
+                                        b: tokens.parse("cauchy coefficient")?,
+                                    }),
+                                    "sellmeier" => {
+                                        let mut coefficient =
+                                            || tokens.parse("sellmeier coefficient");
+                                        let b = [coefficient()?, coefficient()?, coefficient()?];
+                                        let c = [coefficient()?, coefficient()?, coefficient()?];
+                                        dielectric.with_dispersion(Dispersion::Sellmeier { b, c })
+                                    }
+                                    "bk7" => dielectric.with_dispersion(Dispersion::BK7),
+                                    _ => return Err(tokens.error(format!("unexpected {key}"))),
+                                };
+                            }
+                            scene.add_material(dielectric)
+                        }
+                        kind => return Err(tokens.error(format!("unknown material type {kind}"))),
+                    };
+                    names.retain(|(n, _)| n != name);
+                    names.push((name.to_owned(), index));
+                }
+                "sphere" => {
+                    let center = tokens.vec3()?;
+                    let radius = tokens.parse("radius")?;
+                    let name = tokens.word("material name")?;
+                    let Some(&(_, material)) = names.iter().find(|(n, _)| n == name) else {
+                        return Err(tokens.error(format!("unknown material {name}")));
+                    };
+                    scene.add_sphere(center, radius, material);
+                }
+                "random_spheres" => scene.add_random_spheres(tokens.parse("seed")?),
+                _ => return Err(tokens.error(format!("unknown statement {keyword}"))),
+            }
+
+            if let Some(extra) = tokens.iter.next() {
+                return Err(tokens.error(format!("unexpected {extra}")));
+            }
+        }
+        Ok(scene)
+    }
+
+    /// Add a material for the spheres to refer to, returns its index.
+    pub fn add_material(&mut self, material: impl Material + 'static) -> usize {
+        self.materials.push(Arc::new(material));
+        self.materials.len() - 1
+    }
+
+    pub fn add_sphere(&mut self, center: Point3, radius: Float, material: usize) {
+        self.spheres.push((center, radius, material));
+    }
+
+    fn add_random_spheres(&mut self, seed: u64) {
+        util::seed(seed);
+
+        for a in -9..10 {
+            for b in -9..10 {
+                let random_mat = util::random_float();
+                let center = Vec3::new(
+                    (a as f64 + 0.9 * util::random_float()) as Float,
+                    0.2,
+                    (b as f64 + 0.9 * util::random_float()) as Float,
+                );
+
+                let diff = center - Vec3::new(4.0, 0.2, 0.0);
+                if diff.length() > 0.9 {
+                    let material = match random_mat {
+                        x if x < 0.8 => {
+                            let albedo = vec3::random() * vec3::random();
+                            self.add_material(Lambertian::new(albedo))
+                        }
+                        x if x < 0.95 => {
+                            let albedo = vec3::random_min_max(0.5, 1.0);
+                            let fuzz = util::random_min_max(0.0, 0.5) as Float;
+                            self.add_material(Metal::new(albedo, fuzz))
+                        }
+                        _ => self.add_material(Dielectric::new(1.5)),
+                    };
+
+                    self.add_sphere(center, 0.2, material);
+                }
+            }
+        }
+    }
+
+    /// The objects to render, they share their materials with the scene.
+    pub fn world(&self) -> HittableList<Sphere> {
+        let mut world = HittableList::new();
+        for (center, radius, material) in &self.spheres {
+            world.push(Sphere::new(
+                *center,
+                *radius,
+                self.materials[*material].clone(),
+            ));
+        }
+        world
+    }
+}
+
+struct Tokens<'a> {
+    line: usize,
+    iter: std::str::SplitWhitespace<'a>,
+}
+
+impl<'a> Tokens<'a> {
+    fn error(&self, message: String) -> SceneError {
+        SceneError {
+            line: self.line,
+            message,
+        }
+    }
+
+    fn word(&mut self, what: &str) -> Result<&'a str, SceneError> {
+        self.iter
+            .next()
+            .ok_or_else(|| self.error(format!("missing {what}")))
+    }
+
+    fn parse<T: std::str::FromStr>(&mut self, what: &str) -> Result<T, SceneError> {
+        let word = self.word(what)?;
+        word.parse()
+            .map_err(|_| self.error(format!("invalid {what}: {word}")))
+    }
+
+    fn vec3(&mut self) -> Result<Vec3, SceneError> {
+        Ok(Vec3::new(
+            self.parse("vector component")?,
+            self.parse("vector component")?,
+            self.parse("vector component")?,
+        ))
+    }
+
+    fn color(&mut self) -> Result<Color, SceneError> {
+        Ok(Color::new(
+            self.parse("color component")?,
+            self.parse("color component")?,
+            self.parse("color component")?,
+        ))
+    }
+
+    fn aspect_ratio(&mut self) -> Result<f64, SceneError> {
+        let word = self.word("aspect ratio")?;
+        let ratio = match word.split_once(':') {
+            Some((w, h)) => w
+                .parse::<f64>()
+                .and_then(|w| h.parse::<f64>().map(|h| w / h)),
+            None => word.parse(),
+        };
+        ratio.map_err(|_| self.error(format!("invalid aspect ratio: {word}")))
+    }
+}
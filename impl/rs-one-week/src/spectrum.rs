@@ -0,0 +1,122 @@
+use std::sync::OnceLock;
+
+use crate::color::Color;
+
+/// Shortest wavelength traced in spectral mode, in nanometers.
+pub const LAMBDA_MIN: f64 = 360.0;
+/// Longest wavelength traced in spectral mode, in nanometers.
+pub const LAMBDA_MAX: f64 = 830.0;
+
+/// Wavelength for the sample `u` in `0..1`, distributed roughly like the
+/// sensitivity of the eye so that few paths are spent where they barely
+/// show. Returns the wavelength and its density.
+pub fn sample_wavelength(u: f64) -> (f64, f64) {
+    // the fit from pbrt's SampleVisibleWavelengths
+    let lambda = 538.0 - 138.888889 * (0.85691062 - 1.82750197 * u).atanh();
+    let lambda = lambda.clamp(LAMBDA_MIN, LAMBDA_MAX);
+    (lambda, wavelength_pdf(lambda))
+}
+
+fn wavelength_pdf(lambda: f64) -> f64 {
+    let c = (0.0072 * (lambda - 538.0)).cosh();
+    0.0039398042 / (c * c)
+}
+
+/// CIE 1931 2° color matching functions at `lambda`, using the multi-lobe
+/// fit of Wyman, Sloan and Shirley.
+pub fn cie_xyz(lambda: f64) -> Color {
+    let g = |mu: f64, sigma_low: f64, sigma_high: f64| {
+        let sigma = if lambda < mu { sigma_low } else { sigma_high };
+        let t = (lambda - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+    Color::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+fn mat_mul(m: &[Color; 3], v: Color) -> Color {
+    Color::new(m[0].dot(v), m[1].dot(v), m[2].dot(v))
+}
+
+/// Linear sRGB response to `lambda`, before white balancing.
+fn rgb_matching(lambda: f64) -> Color {
+    const XYZ_TO_SRGB: [Color; 3] = [
+        Color::new(3.2404542, -1.5371385, -0.4985314),
+        Color::new(-0.9692660, 1.8760108, 0.0415560),
+        Color::new(0.0556434, -0.2040259, 1.0572252),
+    ];
+    mat_mul(&XYZ_TO_SRGB, cie_xyz(lambda))
+}
+
+/// Smooth blue, green and red spectra that add up to one everywhere, so
+/// white stays white and colors in `0..1` stay valid reflectances.
+fn basis(lambda: f64) -> Color {
+    let step = |center: f64| 1.0 / (1.0 + (-(lambda - center) / 12.0).exp());
+    let (blue_green, green_red) = (step(490.0), step(590.0));
+    Color::new(green_red, blue_green - green_red, 1.0 - blue_green)
+}
+
+struct Tables {
+    /// one over the response of every channel to a constant spectrum of
+    /// one, which white balances the output
+    inv_white: Color,
+    /// turns an RGB color into the weights of the basis spectra
+    rgb_to_basis: [Color; 3],
+}
+
+fn tables() -> &'static Tables {
+    static TABLES: OnceLock<Tables> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        // integrate in 1 nm steps
+        let lambdas = || (LAMBDA_MIN as u32..=LAMBDA_MAX as u32).map(f64::from);
+        let white = lambdas().map(rgb_matching).fold(Color::ZERO, |a, b| a + b);
+        let inv_white = white.map(f64::recip);
+
+        // the color every basis spectrum comes out as, one per column
+        let mut basis_to_rgb = [Color::ZERO; 3];
+        for lambda in lambdas() {
+            let (response, weights) = (rgb_matching(lambda) * inv_white, basis(lambda));
+            for (row, r) in basis_to_rgb.iter_mut().zip(response.to_array()) {
+                *row += weights * r;
+            }
+        }
+
+        Tables {
+            inv_white,
+            rgb_to_basis: invert(&basis_to_rgb),
+        }
+    })
+}
+
+fn invert(m: &[Color; 3]) -> [Color; 3] {
+    // the columns of the inverse are the cross products of the rows
+    let [a, b, c] = *m;
+    let det = a.dot(b.cross(c));
+    let [x, y, z] = [b.cross(c), c.cross(a), a.cross(b)].map(|v| v / det);
+    [
+        Color::new(x.x(), y.x(), z.x()),
+        Color::new(x.y(), y.y(), z.y()),
+        Color::new(x.z(), y.z(), z.z()),
+    ]
+}
+
+/// Value at `lambda` of a smooth spectrum that looks like `rgb`, used for
+/// both reflectances and light. Gray stays flat, and rendering the spectrum
+/// gives back `rgb` up to the clamping of negative values that very
+/// saturated colors need.
+pub fn rgb_to_spectrum(rgb: Color, lambda: f64) -> f64 {
+    let weights = mat_mul(&tables().rgb_to_basis, rgb);
+    weights.dot(basis(lambda)).max(0.0)
+}
+
+/// Linear sRGB estimate of a path that carries `radiance` at `lambda`,
+/// picked with density `pdf`. A flat spectrum averages to gray.
+pub fn to_rgb(radiance: f64, lambda: f64, pdf: f64) -> Color {
+    if pdf <= 0.0 {
+        return Color::ZERO;
+    }
+    rgb_matching(lambda) * tables().inv_white * (radiance / pdf)
+}
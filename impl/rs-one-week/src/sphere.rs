@@ -1,35 +1,69 @@
+use std::{f64::consts::PI, ops::Range, sync::Arc};
+
+use wide::CmpLt;
+
 use crate::{
-    hittable::Hittable,
+    hittable::{self, HitRecord, Hittable},
     material::Material,
-    vec3::{Point3, SliceOp},
+    ray::{LANES, Lanes, Ray, RayPacket},
+    vec3::{Float, Point3, Vec3},
 };
 
-pub struct Sphere<'m> {
+pub struct Sphere {
     center: Point3,
-    radius: f64,
-    material: &'m Material,
+    radius: Float,
+    material: Arc<dyn Material>,
 }
-unsafe impl Send for Sphere<'_> {}
-unsafe impl Sync for Sphere<'_> {}
 
-impl<'m> Sphere<'m> {
-    pub fn new(center: Point3, radius: f64, material: &'m Material) -> Self {
+impl Sphere {
+    pub fn new(center: Point3, radius: Float, material: Arc<dyn Material>) -> Self {
         Self {
             center,
             radius,
             material,
         }
     }
+
+    /// The record for a hit of `r` at `root`.
+    fn record(&self, r: &Ray, root: Float) -> HitRecord<'_> {
+        let offset = r.at(root) - self.center;
+        let outward_normal = offset / self.radius;
+        let mut record = HitRecord::new(r, root, outward_normal, sphere_uv, &*self.material);
+
+        // the root is much less accurate than the point it gives, so the
+        // point is moved back onto the sphere, which leaves the rounding
+        // of the center and radius
+        record.p = self.center + offset * (self.radius.abs() / offset.length());
+        record.p_error =
+            hittable::rounding_error(self.center.map(Float::abs) + Vec3::splat(self.radius.abs()));
+        record
+    }
 }
 
-impl<'m> Hittable<'m> for Sphere<'m> {
-    fn hit(
-        &self,
-        r: &crate::ray::Ray,
-        ray_t: std::ops::Range<f64>,
-        record: &mut crate::hittable::HitRecord,
-    ) -> Option<&'m Material> {
-        let oc = self.center.sub(*r.origin());
+/// Surface coordinates of the point `p` on the unit sphere, `u` goes around
+/// the y axis starting at -x, `v` from the bottom to the top.
+fn sphere_uv(p: Vec3) -> (Float, Float) {
+    // `p` can be a little longer than one
+    let theta = (-p.y()).clamp(-1.0, 1.0).acos();
+    let phi = (-p.z()).atan2(p.x()) + PI as Float;
+    (phi / (2.0 * PI as Float), theta / PI as Float)
+}
+
+/// Nearest root that lies in the acceptable range.
+fn nearest_root(h: Float, sqrtd: Float, a: Float, ray_t: Range<Float>) -> Option<Float> {
+    let mut root = (h - sqrtd) / a;
+    if root <= ray_t.start || ray_t.end <= root {
+        root = (h + sqrtd) / a;
+        if root <= ray_t.start || ray_t.end <= root {
+            return None;
+        }
+    }
+    Some(root)
+}
+
+impl Hittable for Sphere {
+    fn hit(&self, r: &Ray, ray_t: Range<Float>) -> Option<HitRecord<'_>> {
+        let oc = self.center - *r.origin();
         let a = r.direction().len_squared();
         let h = r.direction().dot(oc);
         let c = oc.len_squared() - self.radius * self.radius;
@@ -39,23 +73,42 @@ impl<'m> Hittable<'m> for Sphere<'m> {
             return None;
         }
 
-        let sqrtd = discriminant.sqrt();
+        let root = nearest_root(h, discriminant.sqrt(), a, ray_t)?;
+        Some(self.record(r, root))
+    }
 
-        // find nearest root that lies in the acceptable range
-        let mut root = (h - sqrtd) / a;
-        if root <= ray_t.start || ray_t.end <= root {
-            root = (h + sqrtd) / a;
-            if root <= ray_t.start || ray_t.end <= root {
-                return None;
-            }
-        }
+    #[inline]
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        t_min: Float,
+        t_max: [Float; LANES],
+    ) -> [Option<HitRecord<'_>>; LANES] {
+        // same operations in the same order as `hit`, so that every lane
+        // gets exactly the result of a single ray
+        let [ox, oy, oz] = packet.origin;
+        let [dx, dy, dz] = packet.direction;
+        let center = self.center.to_array().map(Lanes::splat);
+        let (ocx, ocy, ocz) = (center[0] - ox, center[1] - oy, center[2] - oz);
 
-        record.t = root;
-        record.p = r.at(root);
+        let a = dx * dx + dy * dy + dz * dz;
+        let h = dx * ocx + dy * ocy + dz * ocz;
+        let c = (ocx * ocx + ocy * ocy + ocz * ocz) - Lanes::splat(self.radius * self.radius);
+        let discriminant = h * h - a * c;
 
-        let outward_normal = record.p.sub(self.center).div_f(self.radius);
-        record.set_face_normal(r, outward_normal);
+        // most rays miss most spheres, that's where the packet pays off
+        if discriminant.cmp_lt(Lanes::ZERO).all() {
+            return [None; LANES];
+        }
 
-        Some(self.material)
+        let sqrtd = discriminant.sqrt().to_array();
+        let (discriminant, h, a) = (discriminant.to_array(), h.to_array(), a.to_array());
+        std::array::from_fn(|i| {
+            if discriminant[i] < 0.0 {
+                return None;
+            }
+            let root = nearest_root(h[i], sqrtd[i], a[i], t_min..t_max[i])?;
+            Some(self.record(&packet.rays[i], root))
+        })
     }
 }
@@ -0,0 +1,123 @@
+use std::{ops::Range, str::FromStr};
+
+/// A rectangular block of pixels rendered as one unit of work.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x: Range<usize>,
+    pub y: Range<usize>,
+}
+
+impl Tile {
+    /// pixel coordinates in row-major order
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.y
+            .clone()
+            .flat_map(move |j| self.x.clone().map(move |i| (i, j)))
+    }
+}
+
+/// Order in which tiles are handed out to the render threads.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum TileOrder {
+    /// row by row, top to bottom
+    Scanline,
+    /// outwards from the center of the image
+    #[default]
+    Spiral,
+    /// along a Hilbert curve, neighbouring tiles stay close together
+    Hilbert,
+}
+
+impl FromStr for TileOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "scanline" => Ok(Self::Scanline),
+            "spiral" => Ok(Self::Spiral),
+            "hilbert" => Ok(Self::Hilbert),
+            _ => Err(format!("Unknown tile order: {s}")),
+        }
+    }
+}
+
+/// Split a `width` x `height` image into square tiles of `size` pixels
+/// (smaller along the right and bottom edges), listed in `order`.
+pub fn tiles(width: usize, height: usize, size: usize, order: TileOrder) -> Vec<Tile> {
+    let size = size.max(1);
+    let (nx, ny) = (width.div_ceil(size), height.div_ceil(size));
+
+    let cells: Vec<(usize, usize)> = match order {
+        TileOrder::Scanline => (0..ny).flat_map(|y| (0..nx).map(move |x| (x, y))).collect(),
+        TileOrder::Spiral => spiral(nx, ny),
+        TileOrder::Hilbert => {
+            let n = nx.max(ny).next_power_of_two();
+            (0..n * n)
+                .map(|d| hilbert_d2xy(n, d))
+                .filter(|&(x, y)| x < nx && y < ny)
+                .collect()
+        }
+    };
+
+    cells
+        .into_iter()
+        .map(|(x, y)| Tile {
+            x: x * size..((x + 1) * size).min(width),
+            y: y * size..((y + 1) * size).min(height),
+        })
+        .collect()
+}
+
+/// grid cells walked in a square spiral around the center one
+fn spiral(nx: usize, ny: usize) -> Vec<(usize, usize)> {
+    let total = nx * ny;
+    let mut cells = Vec::with_capacity(total);
+    if total == 0 {
+        return cells;
+    }
+
+    let (mut x, mut y) = (((nx - 1) / 2) as isize, ((ny - 1) / 2) as isize);
+    let (mut dx, mut dy) = (1, 0);
+    let mut leg = 1;
+
+    cells.push((x as usize, y as usize));
+    while cells.len() < total {
+        // two legs per ring length: right, down, left, up, ...
+        for _ in 0..2 {
+            for _ in 0..leg {
+                x += dx;
+                y += dy;
+                if (0..nx as isize).contains(&x) && (0..ny as isize).contains(&y) {
+                    cells.push((x as usize, y as usize));
+                }
+            }
+            (dx, dy) = (-dy, dx);
+        }
+        leg += 1;
+    }
+    cells
+}
+
+/// position of the `d`-th cell along a Hilbert curve filling an `n` x `n`
+/// grid, `n` being a power of two
+fn hilbert_d2xy(n: usize, d: usize) -> (usize, usize) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+    while s < n {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
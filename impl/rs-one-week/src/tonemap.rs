@@ -0,0 +1,145 @@
+use std::str::FromStr;
+
+use crate::color::Color;
+
+/// Curve used to squeeze scene radiance into the displayable `0..1` range.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum ToneMap {
+    /// no compression, everything above 1.0 is clipped
+    #[default]
+    Clamp,
+    /// `L / (1 + L)` on luminance
+    Reinhard,
+    /// Reinhard with a white point: luminance at or above it maps to 1.0
+    ExtendedReinhard(f64),
+    /// Stephen Hill's fit of the ACES RRT + sRGB ODT
+    Aces,
+    /// minimal AgX approximation with the default look
+    AgX,
+}
+
+impl FromStr for ToneMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (s, None),
+        };
+
+        match (name.to_ascii_lowercase().as_str(), arg) {
+            ("clamp" | "none", None) => Ok(Self::Clamp),
+            ("reinhard", None) => Ok(Self::Reinhard),
+            ("extended-reinhard", white) => {
+                let white: f64 = match white {
+                    Some(w) => w.parse().map_err(|_| format!("Invalid white point: {w}"))?,
+                    None => 4.0,
+                };
+                if !(white > 0.0 && white.is_finite()) {
+                    return Err(format!("White point must be positive and finite: {white}"));
+                }
+                Ok(Self::ExtendedReinhard(white))
+            }
+            ("aces", None) => Ok(Self::Aces),
+            ("agx", None) => Ok(Self::AgX),
+            _ => Err(format!("Unknown tone map operator: {s}")),
+        }
+    }
+}
+
+/// Exposure and tone curve applied when a render is written to an 8-bit image.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct ToneMapping {
+    pub operator: ToneMap,
+    /// exposure compensation in stops (EV), 0 leaves radiance untouched
+    pub exposure: f64,
+}
+
+impl ToneMapping {
+    /// Map linear scene radiance to linear display values in `0..1`.
+    pub fn apply(&self, color: Color) -> Color {
+        let color = color * self.exposure.exp2();
+
+        let mapped = match self.operator {
+            ToneMap::Clamp => color,
+            ToneMap::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            ToneMap::ExtendedReinhard(white) => {
+                let white_sq = white * white;
+                scale_luminance(color, |l| l * (1.0 + l / white_sq) / (1.0 + l))
+            }
+            ToneMap::Aces => aces_fitted(color),
+            ToneMap::AgX => agx(color),
+        };
+
+        mapped.map(|c| c.clamp(0.0, 1.0))
+    }
+}
+
+/// Rec. 709 relative luminance of a linear color.
+pub fn luminance(color: Color) -> f64 {
+    color.dot(Color::new(0.2126, 0.7152, 0.0722))
+}
+
+fn scale_luminance(color: Color, curve: impl Fn(f64) -> f64) -> Color {
+    let l = luminance(color);
+    if l <= 0.0 {
+        return Color::ZERO;
+    }
+    color * (curve(l) / l)
+}
+
+fn mat_mul(m: &[Color; 3], v: Color) -> Color {
+    Color::new(m[0].dot(v), m[1].dot(v), m[2].dot(v))
+}
+
+fn aces_fitted(color: Color) -> Color {
+    // sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT
+    const INPUT: [Color; 3] = [
+        Color::new(0.59719, 0.35458, 0.04823),
+        Color::new(0.07600, 0.90834, 0.01566),
+        Color::new(0.02840, 0.13383, 0.83777),
+    ];
+    // ODT_SAT => XYZ => D60_2_D65 => sRGB
+    const OUTPUT: [Color; 3] = [
+        Color::new(1.60475, -0.53108, -0.07367),
+        Color::new(-0.10208, 1.10813, -0.00605),
+        Color::new(-0.00327, -0.07276, 1.07602),
+    ];
+
+    let rrt_and_odt_fit = |v: f64| {
+        let a = v * (v + 0.0245786) - 0.000090537;
+        let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+        a / b
+    };
+
+    mat_mul(&OUTPUT, mat_mul(&INPUT, color).map(rrt_and_odt_fit))
+}
+
+fn agx(color: Color) -> Color {
+    const INSET: [Color; 3] = [
+        Color::new(0.842479062253094, 0.0784335999999992, 0.0792237451477643),
+        Color::new(0.0423282422610123, 0.878468636469772, 0.0791661274605434),
+        Color::new(0.0423756549057051, 0.0784336, 0.879142973793104),
+    ];
+    const OUTSET: [Color; 3] = [
+        Color::new(1.19687900512017, -0.0980208811401368, -0.0990297440797205),
+        Color::new(-0.0528968517574562, 1.15190312990417, -0.0989611768448433),
+        Color::new(-0.0529716355144438, -0.0980434501171241, 1.15107367264116),
+    ];
+    const MIN_EV: f64 = -12.47393;
+    const MAX_EV: f64 = 4.026069;
+
+    // encode in log2 space between the exposure bounds, then apply the sigmoid
+    let contrast = |c: f64| {
+        let x = (c.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    };
+
+    let mapped = mat_mul(&OUTSET, mat_mul(&INSET, color).map(contrast));
+
+    // the curve targets a 2.2 display, bring it back to linear
+    mapped.map(|c| c.max(0.0).powf(2.2))
+}
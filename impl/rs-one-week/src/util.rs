@@ -1,14 +1,32 @@
 use std::f64::consts::PI;
 
-use rand::Rng;
+use crate::{sampler, vec3::Float};
 
-pub fn degrees_to_radians(degrees: f64) -> f64 {
-    degrees * PI / 180.0
+pub fn degrees_to_radians(degrees: Float) -> Float {
+    degrees * PI as Float / 180.0
 }
 
+/// SplitMix64 finalizer, spreads nearby integers over the whole 64 bit range.
+pub fn mix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Restart this thread's generator from `seed`.
+pub fn seed(seed: u64) {
+    sampler::seed(seed);
+}
+
+/// Next dimension of the current sample, see [`sampler::get_1d`].
 pub fn random_float() -> f64 {
-    let mut rng = rand::rng();
-    rng.random_range(0.0..1.0)
+    sampler::get_1d()
+}
+
+/// Next two dimensions of the current sample, see [`sampler::get_2d`].
+pub fn random_2d() -> (f64, f64) {
+    sampler::get_2d()
 }
 
 pub fn random_min_max(min: f64, max: f64) -> f64 {
@@ -1,141 +1,494 @@
+use std::{
+    f64::consts::{FRAC_PI_2, FRAC_PI_4, PI},
+    fmt,
+    iter::Sum,
+    ops::{Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub, SubAssign},
+};
+
 use crate::util;
 
-pub type Vec3 = [f64; 3];
-pub type Point3 = Vec3;
+/// Generates a 3 component vector over `$float`.
+///
+/// The components live in a plain array, or with the `simd` feature in the
+/// first three lanes of a `wide` vector (the fourth lane stays zero), which
+/// maps onto SSE/AVX on x86_64 and NEON on aarch64. Only a handful of
+/// methods look at the storage, everything else is built on top of them.
+macro_rules! vec3_type {
+    ($name:ident, $float:ty, $simd:ident) => {
+        #[cfg(not(feature = "simd"))]
+        #[derive(Clone, Copy)]
+        pub struct $name([$float; 3]);
 
-pub fn init() -> Vec3 {
-    [0.0, 0.0, 0.0]
-}
+        #[cfg(feature = "simd")]
+        #[derive(Clone, Copy)]
+        pub struct $name(wide::$simd);
 
-pub fn random() -> Vec3 {
-    [
-        util::random_float(),
-        util::random_float(),
-        util::random_float(),
-    ]
-}
+        #[cfg(not(feature = "simd"))]
+        impl $name {
+            #[inline]
+            pub const fn new(x: $float, y: $float, z: $float) -> Self {
+                Self([x, y, z])
+            }
 
-pub fn random_min_max(min: f64, max: f64) -> Vec3 {
-    [
-        util::random_min_max(min, max),
-        util::random_min_max(min, max),
-        util::random_min_max(min, max),
-    ]
-}
+            #[inline]
+            pub fn splat(v: $float) -> Self {
+                Self([v; 3])
+            }
 
-pub fn random_unit_vector() -> Vec3 {
-    loop {
-        let p = random_min_max(-1.0, 1.0);
-        let len_sq = p.len_squared();
-        // prevent fp leak; very small value can underflow to zero
-        // use 1^-160 as min bound
-        if 1e-160 < len_sq && len_sq <= 1.0 {
-            return p.div_f(len_sq.sqrt());
+            #[inline]
+            pub fn to_array(self) -> [$float; 3] {
+                self.0
+            }
+
+            #[inline]
+            fn zip(self, rhs: Self, f: impl Fn($float, $float) -> $float) -> Self {
+                let [a, b] = [self.0, rhs.0];
+                Self([f(a[0], b[0]), f(a[1], b[1]), f(a[2], b[2])])
+            }
+
+            #[inline]
+            pub fn dot(self, rhs: Self) -> $float {
+                self.0[0] * rhs.0[0] + self.0[1] * rhs.0[1] + self.0[2] * rhs.0[2]
+            }
         }
-    }
-}
 
-pub fn random_in_unit_disk() -> Vec3 {
-    loop {
-        let p = [
-            util::random_min_max(-1.0, 1.0),
-            util::random_min_max(-1.0, 1.0),
-            0.0,
-        ];
-        if p.len_squared() < 1.0 {
-            return p;
+        #[cfg(feature = "simd")]
+        impl $name {
+            #[inline]
+            pub const fn new(x: $float, y: $float, z: $float) -> Self {
+                Self(wide::$simd::new([x, y, z, 0.0]))
+            }
+
+            #[inline]
+            pub fn splat(v: $float) -> Self {
+                Self::new(v, v, v)
+            }
+
+            #[inline]
+            pub fn to_array(self) -> [$float; 3] {
+                let [x, y, z, _] = self.0.to_array();
+                [x, y, z]
+            }
+
+            #[inline]
+            fn zip(self, rhs: Self, f: impl Fn($float, $float) -> $float) -> Self {
+                let [a, b] = [self.to_array(), rhs.to_array()];
+                Self::new(f(a[0], b[0]), f(a[1], b[1]), f(a[2], b[2]))
+            }
+
+            #[inline]
+            pub fn dot(self, rhs: Self) -> $float {
+                // summed in the same order as the array backend, both give
+                // bit-identical images
+                let [x, y, z, _] = (self.0 * rhs.0).to_array();
+                x + y + z
+            }
         }
-    }
-}
 
-pub trait SliceOp {
-    fn len_squared(&self) -> f64;
-    fn length(&self) -> f64;
-    fn dot(self, rhs: Vec3) -> f64;
-    fn cross(self, rhs: Vec3) -> Vec3;
-    fn unit_vec(self) -> Vec3;
-    fn near_zero(&self) -> bool;
-    fn reflect(self, n: Vec3) -> Vec3;
-    fn refract(self, n: Vec3, etai_over_etat: f64) -> Vec3;
-
-    fn neg(&self) -> Vec3;
-    fn add(self, rhs: Vec3) -> Vec3;
-    fn add_assign(&mut self, rhs: Vec3);
-    fn sub(self, rhs: Vec3) -> Vec3;
-    fn mul(self, rhs: Vec3) -> Vec3;
-    fn mul_f(self, rhs: f64) -> Vec3;
-    fn div_f(self, rhs: f64) -> Vec3;
-}
+        #[cfg(not(feature = "simd"))]
+        impl Index<usize> for $name {
+            type Output = $float;
 
-impl SliceOp for Vec3 {
-    fn len_squared(&self) -> f64 {
-        self[0] * self[0] + self[1] * self[1] + self[2] * self[2]
-    }
+            #[inline]
+            fn index(&self, i: usize) -> &$float {
+                &self.0[i]
+            }
+        }
 
-    fn length(&self) -> f64 {
-        self.len_squared().sqrt()
-    }
+        #[cfg(not(feature = "simd"))]
+        impl IndexMut<usize> for $name {
+            #[inline]
+            fn index_mut(&mut self, i: usize) -> &mut $float {
+                &mut self.0[i]
+            }
+        }
 
-    fn dot(self, rhs: Vec3) -> f64 {
-        self[0] * rhs[0] + self[1] * rhs[1] + self[2] * rhs[2]
-    }
+        #[cfg(not(feature = "simd"))]
+        impl Neg for $name {
+            type Output = Self;
 
-    fn cross(self, rhs: Vec3) -> Vec3 {
-        [
-            self[1] * rhs[2] - self[2] * rhs[1],
-            self[2] * rhs[0] - self[0] * rhs[2],
-            self[0] * rhs[1] - self[1] * rhs[0],
-        ]
-    }
+            #[inline]
+            fn neg(self) -> Self {
+                self.map(|c| -c)
+            }
+        }
 
-    fn unit_vec(self) -> Vec3 {
-        let len = self.length();
-        self.div_f(len)
-    }
+        #[cfg(not(feature = "simd"))]
+        impl Add for $name {
+            type Output = Self;
 
-    fn near_zero(&self) -> bool {
-        let s = 1e-8;
-        self[0].abs() < s && self[1].abs() < s && self[2].abs() < s
-    }
+            #[inline]
+            fn add(self, rhs: Self) -> Self {
+                self.zip(rhs, |a, b| a + b)
+            }
+        }
 
-    fn reflect(self, n: Vec3) -> Vec3 {
-        self.sub(n.mul_f(2.0 * self.dot(n)))
-    }
+        #[cfg(not(feature = "simd"))]
+        impl Sub for $name {
+            type Output = Self;
 
-    fn refract(self, n: Vec3, etai_over_etat: f64) -> Vec3 {
-        let cos_theta = self.neg().dot(n).min(1.0);
-        let r_out_perp = self.add(n.mul_f(cos_theta)).mul_f(etai_over_etat);
-        let r_out_parallel = n.mul_f(-(1.0 - r_out_perp.len_squared()).abs().sqrt());
-        r_out_perp.add(r_out_parallel)
-    }
+            #[inline]
+            fn sub(self, rhs: Self) -> Self {
+                self.zip(rhs, |a, b| a - b)
+            }
+        }
 
-    fn neg(&self) -> Vec3 {
-        [-self[0], -self[1], -self[2]]
-    }
+        /// component-wise product, as used for colors
+        #[cfg(not(feature = "simd"))]
+        impl Mul for $name {
+            type Output = Self;
 
-    fn add(self, rhs: Vec3) -> Vec3 {
-        [self[0] + rhs[0], self[1] + rhs[1], self[2] + rhs[2]]
-    }
+            #[inline]
+            fn mul(self, rhs: Self) -> Self {
+                self.zip(rhs, |a, b| a * b)
+            }
+        }
 
-    fn add_assign(&mut self, rhs: Vec3) {
-        self[0] += rhs[0];
-        self[1] += rhs[1];
-        self[2] += rhs[2];
-    }
+        #[cfg(not(feature = "simd"))]
+        impl Mul<$float> for $name {
+            type Output = Self;
 
-    fn sub(self, rhs: Vec3) -> Vec3 {
-        [self[0] - rhs[0], self[1] - rhs[1], self[2] - rhs[2]]
-    }
+            #[inline]
+            fn mul(self, rhs: $float) -> Self {
+                self.map(|c| c * rhs)
+            }
+        }
+
+        #[cfg(feature = "simd")]
+        impl Index<usize> for $name {
+            type Output = $float;
+
+            #[inline]
+            fn index(&self, i: usize) -> &$float {
+                &self.0.as_array_ref()[..3][i]
+            }
+        }
+
+        #[cfg(feature = "simd")]
+        impl IndexMut<usize> for $name {
+            #[inline]
+            fn index_mut(&mut self, i: usize) -> &mut $float {
+                &mut self.0.as_array_mut()[..3][i]
+            }
+        }
+
+        #[cfg(feature = "simd")]
+        impl Neg for $name {
+            type Output = Self;
+
+            #[inline]
+            fn neg(self) -> Self {
+                Self(-self.0)
+            }
+        }
+
+        #[cfg(feature = "simd")]
+        impl Add for $name {
+            type Output = Self;
+
+            #[inline]
+            fn add(self, rhs: Self) -> Self {
+                Self(self.0 + rhs.0)
+            }
+        }
+
+        #[cfg(feature = "simd")]
+        impl Sub for $name {
+            type Output = Self;
+
+            #[inline]
+            fn sub(self, rhs: Self) -> Self {
+                Self(self.0 - rhs.0)
+            }
+        }
+
+        /// component-wise product, as used for colors
+        #[cfg(feature = "simd")]
+        impl Mul for $name {
+            type Output = Self;
+
+            #[inline]
+            fn mul(self, rhs: Self) -> Self {
+                Self(self.0 * rhs.0)
+            }
+        }
+
+        #[cfg(feature = "simd")]
+        impl Mul<$float> for $name {
+            type Output = Self;
+
+            #[inline]
+            fn mul(self, rhs: $float) -> Self {
+                Self(self.0 * wide::$simd::splat(rhs))
+            }
+        }
+
+        impl $name {
+            pub const ZERO: Self = Self::new(0.0, 0.0, 0.0);
+            pub const ONE: Self = Self::new(1.0, 1.0, 1.0);
+
+            #[inline]
+            pub fn x(self) -> $float {
+                self[0]
+            }
 
-    fn mul(self, rhs: Vec3) -> Vec3 {
-        [self[0] * rhs[0], self[1] * rhs[1], self[2] * rhs[2]]
+            #[inline]
+            pub fn y(self) -> $float {
+                self[1]
+            }
+
+            #[inline]
+            pub fn z(self) -> $float {
+                self[2]
+            }
+
+            #[inline]
+            pub fn len_squared(self) -> $float {
+                self.dot(self)
+            }
+
+            #[inline]
+            pub fn length(self) -> $float {
+                self.len_squared().sqrt()
+            }
+
+            #[inline]
+            pub fn cross(self, rhs: Self) -> Self {
+                let [a, b] = [self.to_array(), rhs.to_array()];
+                Self::new(
+                    a[1] * b[2] - a[2] * b[1],
+                    a[2] * b[0] - a[0] * b[2],
+                    a[0] * b[1] - a[1] * b[0],
+                )
+            }
+
+            #[inline]
+            pub fn unit_vec(self) -> Self {
+                self / self.length()
+            }
+
+            #[inline]
+            pub fn near_zero(self) -> bool {
+                let s = 1e-8;
+                self.to_array().iter().all(|c| c.abs() < s)
+            }
+
+            #[inline]
+            pub fn min(self, rhs: Self) -> Self {
+                self.zip(rhs, <$float>::min)
+            }
+
+            #[inline]
+            pub fn max(self, rhs: Self) -> Self {
+                self.zip(rhs, <$float>::max)
+            }
+
+            #[inline]
+            pub fn map(self, f: impl Fn($float) -> $float) -> Self {
+                let [x, y, z] = self.to_array();
+                Self::new(f(x), f(y), f(z))
+            }
+
+            #[inline]
+            pub fn reflect(self, n: Self) -> Self {
+                self - n * (2.0 * self.dot(n))
+            }
+
+            #[inline]
+            pub fn refract(self, n: Self, etai_over_etat: $float) -> Self {
+                let cos_theta = (-self).dot(n).min(1.0);
+                let r_out_perp = (self + n * cos_theta) * etai_over_etat;
+                let r_out_parallel = n * -(1.0 - r_out_perp.len_squared()).abs().sqrt();
+                r_out_perp + r_out_parallel
+            }
+        }
+
+        impl Default for $name {
+            #[inline]
+            fn default() -> Self {
+                Self::ZERO
+            }
+        }
+
+        impl PartialEq for $name {
+            #[inline]
+            fn eq(&self, rhs: &Self) -> bool {
+                self.to_array() == rhs.to_array()
+            }
+        }
+
+        impl fmt::Debug for $name {
+            #[inline]
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let [x, y, z] = self.to_array();
+                f.debug_tuple(stringify!($name))
+                    .field(&x)
+                    .field(&y)
+                    .field(&z)
+                    .finish()
+            }
+        }
+
+        impl From<[$float; 3]> for $name {
+            #[inline]
+            fn from([x, y, z]: [$float; 3]) -> Self {
+                Self::new(x, y, z)
+            }
+        }
+
+        impl From<$name> for [$float; 3] {
+            #[inline]
+            fn from(v: $name) -> Self {
+                v.to_array()
+            }
+        }
+
+        impl Mul<$name> for $float {
+            type Output = $name;
+
+            #[inline]
+            fn mul(self, rhs: $name) -> $name {
+                rhs * self
+            }
+        }
+
+        impl Div<$float> for $name {
+            type Output = Self;
+
+            #[inline]
+            fn div(self, rhs: $float) -> Self {
+                self * (1.0 / rhs)
+            }
+        }
+
+        impl AddAssign for $name {
+            #[inline]
+            fn add_assign(&mut self, rhs: Self) {
+                *self = *self + rhs;
+            }
+        }
+
+        impl SubAssign for $name {
+            #[inline]
+            fn sub_assign(&mut self, rhs: Self) {
+                *self = *self - rhs;
+            }
+        }
+
+        impl MulAssign<$float> for $name {
+            #[inline]
+            fn mul_assign(&mut self, rhs: $float) {
+                *self = *self * rhs;
+            }
+        }
+
+        impl DivAssign<$float> for $name {
+            #[inline]
+            fn div_assign(&mut self, rhs: $float) {
+                *self = *self / rhs;
+            }
+        }
+
+        impl Sum for $name {
+            #[inline]
+            fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+                iter.fold(Self::ZERO, |acc, v| acc + v)
+            }
+        }
+    };
+}
+
+vec3_type!(Vec3d, f64, f64x4);
+vec3_type!(Vec3f, f32, f32x4);
+
+impl Vec3d {
+    pub fn to_f64(self) -> Vec3d {
+        self
     }
+}
 
-    fn mul_f(self, rhs: f64) -> Vec3 {
-        [self[0] * rhs, self[1] * rhs, self[2] * rhs]
+impl Vec3f {
+    /// Widen to `f64`, where geometry meets colors.
+    pub fn to_f64(self) -> Vec3d {
+        let [x, y, z] = self.to_array();
+        Vec3d::new(x.into(), y.into(), z.into())
     }
+}
+
+/// Precision of the geometry: positions, directions, distances and
+/// everything else a ray meets on its way. Colors and the accumulated
+/// pixels stay `f64`.
+///
+/// The `f32` feature halves the size of the scene data at the cost of
+/// precision, scattered rays are moved off surfaces by bounds that grow to
+/// match.
+#[cfg(not(feature = "f32"))]
+pub type Float = f64;
+#[cfg(feature = "f32")]
+pub type Float = f32;
+
+/// Widen a [`Float`] to `f64`, like `Vec3::to_f64`.
+#[cfg(not(feature = "f32"))]
+pub fn to_f64(x: Float) -> f64 {
+    x
+}
+#[cfg(feature = "f32")]
+pub fn to_f64(x: Float) -> f64 {
+    x.into()
+}
+
+/// The vector type of the geometry, see [`Float`].
+#[cfg(not(feature = "f32"))]
+pub type Vec3 = Vec3d;
+#[cfg(feature = "f32")]
+pub type Vec3 = Vec3f;
+
+pub type Point3 = Vec3;
+
+/// Random color, every channel in `0..1`.
+pub fn random() -> Vec3d {
+    Vec3d::new(
+        util::random_float(),
+        util::random_float(),
+        util::random_float(),
+    )
+}
+
+/// Random color, every channel in `min..max`.
+pub fn random_min_max(min: f64, max: f64) -> Vec3d {
+    Vec3d::new(
+        util::random_min_max(min, max),
+        util::random_min_max(min, max),
+        util::random_min_max(min, max),
+    )
+}
+
+pub fn random_unit_vector() -> Vec3 {
+    // map a 2d sample straight onto the sphere, so that every scatter
+    // consumes exactly two sample dimensions
+    let (u, v) = util::random_2d();
+    let z = 1.0 - 2.0 * u;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+    Vec3::new(
+        (r * phi.cos()) as Float,
+        (r * phi.sin()) as Float,
+        z as Float,
+    )
+}
 
-    fn div_f(self, rhs: f64) -> Vec3 {
-        self.mul_f(1.0 / rhs)
+pub fn random_in_unit_disk() -> Vec3 {
+    // concentric mapping of the unit square, see Shirley & Chiu (1997)
+    let (u, v) = util::random_2d();
+    let (x, y) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+    if x == 0.0 && y == 0.0 {
+        return Vec3::ZERO;
     }
+
+    let (r, theta) = if x.abs() > y.abs() {
+        (x, FRAC_PI_4 * (y / x))
+    } else {
+        (y, FRAC_PI_2 - FRAC_PI_4 * (x / y))
+    };
+    Vec3::new((r * theta.cos()) as Float, (r * theta.sin()) as Float, 0.0)
 }
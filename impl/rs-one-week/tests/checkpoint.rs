@@ -0,0 +1,95 @@
+//! Resuming a checkpoint has to give the same film as rendering all the
+//! samples in one go.
+
+use std::{path::PathBuf, time::Duration};
+
+use rt_rs::{
+    Error, RenderOptions, Scene, aov::AovKind, camera::CheckpointOptions, film::Film,
+    sampler::SamplerKind,
+};
+
+const SCENE: &str = "
+image 24 3:2
+max_depth 8
+camera look_from 13 2 3 look_at 0 0 0 vfov 30 defocus_angle 0.6 focus_dist 10
+material ground lambertian 0.5 0.5 0.5
+sphere 0 -1000 0 1000 ground
+random_spheres 0
+material glass dielectric 1.5
+sphere 0 1 0 1 glass
+";
+
+fn render(samples: u32, options: &RenderOptions) -> rt_rs::Result<Film> {
+    let scene = Scene::parse(&format!("samples {samples}\n{SCENE}")).unwrap();
+    let camera = scene.camera.camera().unwrap();
+    camera.render_film(&scene.world(), options, |_| Ok(()))
+}
+
+/// The whole state of the film, as the checkpoint stores it.
+fn bytes(film: &Film) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for stats in film.pixels() {
+        stats.write_to(&mut bytes).unwrap();
+    }
+    for aov in film.aovs() {
+        aov.write_to(&mut bytes).unwrap();
+    }
+    bytes
+}
+
+/// A checkpoint file only this test uses.
+fn checkpoint_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rt-rs-{}-{name}.ckpt", std::process::id()))
+}
+
+/// Render 4 samples per pixel into a checkpoint and resume it to 8.
+fn resume_from_4_to_8(name: &str, options: &RenderOptions) -> rt_rs::Result<Film> {
+    let path = checkpoint_path(name).to_string_lossy().into_owned();
+    let first = RenderOptions {
+        checkpoint: Some(CheckpointOptions {
+            path: path.clone(),
+            interval: Duration::from_secs(3600),
+        }),
+        ..options.clone()
+    };
+    render(4, &first)?;
+
+    let resumed = RenderOptions {
+        resume: Some(path.clone()),
+        ..options.clone()
+    };
+    let film = render(8, &resumed);
+    std::fs::remove_file(&path).unwrap();
+    film
+}
+
+#[test]
+fn resuming_matches_a_straight_render() {
+    for sampler in [SamplerKind::Random, SamplerKind::Sobol, SamplerKind::Halton] {
+        let options = RenderOptions {
+            sampler,
+            tile_size: 7,
+            aovs: AovKind::ALL.to_vec(),
+            ..RenderOptions::default()
+        };
+        let straight = render(8, &options).unwrap();
+        let resumed = resume_from_4_to_8(&format!("{sampler:?}"), &options).unwrap();
+        assert!(
+            bytes(&straight) == bytes(&resumed),
+            "resumed {sampler:?} render differs"
+        );
+    }
+}
+
+#[test]
+fn stratified_renders_keep_their_target() {
+    let options = RenderOptions {
+        sampler: SamplerKind::Stratified,
+        ..RenderOptions::default()
+    };
+    let result = resume_from_4_to_8("stratified", &options);
+    assert!(
+        matches!(result, Err(Error::Checkpoint(_))),
+        "stratified checkpoint resumed with a new target"
+    );
+}
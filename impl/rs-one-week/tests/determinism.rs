@@ -0,0 +1,78 @@
+//! Renders must not depend on how the work is scheduled, the regression
+//! tests compare images bit for bit.
+
+use rt_rs::{FrameBuffer, RenderOptions, Scene, sampler::SamplerKind};
+
+const SCENE: &str = "
+image 24 3:2
+samples 4
+max_depth 8
+camera look_from 13 2 3 look_at 0 0 0 vfov 30 defocus_angle 0.6 focus_dist 10
+material ground lambertian 0.5 0.5 0.5
+sphere 0 -1000 0 1000 ground
+random_spheres 0
+material glass dielectric 1.5 roughness 0.3
+material gold conductor gold 0.2
+sphere 0 1 0 1 glass
+sphere 4 1 0 1 gold
+";
+
+fn render(threads: usize, options: &RenderOptions) -> FrameBuffer {
+    let scene = Scene::parse(SCENE).unwrap();
+    let camera = scene.camera.camera().unwrap();
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .unwrap();
+    pool.install(|| camera.render(&scene.world(), options))
+        .unwrap()
+}
+
+fn bits(fb: &FrameBuffer) -> Vec<u64> {
+    fb.pixels()
+        .iter()
+        .flat_map(|c| c.to_array().map(f64::to_bits))
+        .collect()
+}
+
+fn assert_schedule_independent(options: RenderOptions) {
+    let reference = bits(&render(1, &options));
+    assert_eq!(reference, bits(&render(4, &options)), "4 threads");
+
+    let scalar = RenderOptions {
+        ray_packets: false,
+        ..options.clone()
+    };
+    assert_eq!(reference, bits(&render(1, &scalar)), "without packets");
+    assert_eq!(
+        reference,
+        bits(&render(3, &scalar)),
+        "3 threads without packets"
+    );
+}
+
+#[test]
+fn rgb_render_is_deterministic() {
+    assert_schedule_independent(RenderOptions {
+        tile_size: 5,
+        ..RenderOptions::default()
+    });
+}
+
+#[test]
+fn sobol_render_is_deterministic() {
+    assert_schedule_independent(RenderOptions {
+        sampler: SamplerKind::Sobol,
+        tile_size: 7,
+        ..RenderOptions::default()
+    });
+}
+
+#[test]
+fn spectral_render_is_deterministic() {
+    assert_schedule_independent(RenderOptions {
+        spectral: true,
+        tile_size: 5,
+        ..RenderOptions::default()
+    });
+}
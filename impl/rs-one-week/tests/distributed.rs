@@ -0,0 +1,85 @@
+//! A render split over workers on localhost has to come out the same as a
+//! local one, bit for bit.
+
+use std::{net::TcpListener, sync::mpsc, thread};
+
+use rt_rs::{
+    RenderOptions, Scene,
+    aov::AovKind,
+    distributed,
+    film::Film,
+    progress::{ProgressCallback, ProgressEvent},
+    sampler::SamplerKind,
+};
+
+const SCENE: &str = "
+image 24 3:2
+samples 4
+max_depth 8
+camera look_from 13 2 3 look_at 0 0 0 vfov 30 defocus_angle 0.6 focus_dist 10
+material ground lambertian 0.5 0.5 0.5
+sphere 0 -1000 0 1000 ground
+random_spheres 0
+material glass dielectric 1.5
+sphere 0 1 0 1 glass
+";
+
+/// The whole state of the film, as workers send it back.
+fn bytes(film: &Film) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for stats in film.pixels() {
+        stats.write_to(&mut bytes).unwrap();
+    }
+    for aov in film.aovs() {
+        aov.write_to(&mut bytes).unwrap();
+    }
+    bytes
+}
+
+#[test]
+fn two_workers_render_the_same_image_as_a_local_render() {
+    let scene = Scene::parse(SCENE).unwrap();
+    let camera = scene.camera.camera().unwrap();
+    let world = scene.world();
+    let options = RenderOptions {
+        sampler: SamplerKind::Sobol,
+        tile_size: 5,
+        aovs: AovKind::ALL.to_vec(),
+        ..RenderOptions::default()
+    };
+    let local = camera.render_film(&world, &options, |_| Ok(())).unwrap();
+
+    // the coordinator picks a free port and tells it through its progress
+    let (addrs, addr) = mpsc::channel();
+    let coordinator_options = RenderOptions {
+        progress: Some(ProgressCallback::new(move |event| {
+            if let ProgressEvent::Listening { addr } = event {
+                addrs.send(addr.clone()).unwrap();
+            }
+        })),
+        ..options.clone()
+    };
+    let scene_hash = distributed::scene_hash(SCENE);
+
+    let (film, addr) = thread::scope(|scope| {
+        let coordinator = scope.spawn(|| {
+            distributed::coordinate("127.0.0.1:0", &camera, scene_hash, &coordinator_options)
+        });
+        let addr = addr.recv().unwrap();
+        let workers: Vec<_> = (0..2)
+            .map(|_| {
+                let (addr, camera, world) = (addr.clone(), &camera, &world);
+                scope.spawn(move || distributed::work(&addr, camera, world, scene_hash, 1))
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap().unwrap();
+        }
+        (coordinator.join().unwrap().unwrap(), addr)
+    });
+
+    assert!(bytes(&local) == bytes(&film), "distributed render differs");
+
+    // the coordinator stops listening once it is done
+    TcpListener::bind(&addr).unwrap();
+}
@@ -0,0 +1,195 @@
+//! The rough materials weight their scattered rays by the BSDF times the
+//! cosine over the density they sample with, and `Material::pdf` has to
+//! be that density.
+
+use std::f64::consts::PI;
+
+use rt_rs::{
+    ComplexIor, Conductor, Dielectric, Material,
+    color::Color,
+    hittable::HitRecord,
+    microfacet::{self, Ggx},
+    ray::Ray,
+    util,
+    vec3::{self, Float, Vec3},
+};
+
+const INCIDENT_ANGLES: [f64; 3] = [0.1, 0.8, 1.3];
+
+/// A hit from above on the xy plane at the origin, the normal is `z` so
+/// world and local directions are the same.
+fn hit(material: &dyn Material, theta: f64) -> (Ray, HitRecord<'_>) {
+    let from = Vec3::new(theta.sin() as Float, 0.0, theta.cos() as Float);
+    let r_in = Ray::new(from, -from);
+    let record = HitRecord::new(
+        &r_in,
+        1.0,
+        Vec3::new(0.0, 0.0, 1.0),
+        |_| (0.0, 0.0),
+        material,
+    );
+    (r_in, record)
+}
+
+fn local(v: &Vec3) -> [f64; 3] {
+    v.unit_vec().to_f64().to_array()
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(v: [f64; 3]) -> [f64; 3] {
+    let len = dot(v, v).sqrt();
+    v.map(|c| c / len)
+}
+
+/// The distribution and the masking of the directions, in local
+/// coordinates.
+fn ggx_terms(ggx: &Ggx, wo: [f64; 3], wi: [f64; 3], wm: [f64; 3]) -> (f64, f64) {
+    let v = |[x, y, z]: [f64; 3]| Vec3::new(x as Float, y as Float, z as Float);
+    let (wo, wi, wm) = (v(wo), v(wi), v(wm));
+    (vec3::to_f64(ggx.d(wm)), vec3::to_f64(ggx.g2(wo, wi)))
+}
+
+/// The BSDF of a rough conductor times the cosine of `wi`.
+fn conductor_f_cos(conductor: &Conductor, wo: [f64; 3], wi: [f64; 3]) -> [f64; 3] {
+    let ggx = Ggx::from_roughness(conductor.roughness);
+    let wm = normalize([wo[0] + wi[0], wo[1] + wi[1], wo[2] + wi[2]]);
+    let (d, g2) = ggx_terms(&ggx, wo, wi, wm);
+    let fresnel = microfacet::fresnel_conductor(dot(wo, wm), conductor.ior.eta, conductor.ior.k);
+    fresnel.to_array().map(|f| f * d * g2 / (4.0 * wo[2]))
+}
+
+/// The BSDF of a rough dielectric hit from outside times the cosine of
+/// `wi`, for reflection and refraction.
+fn dielectric_f_cos(dielectric: &Dielectric, wo: [f64; 3], wi: [f64; 3]) -> f64 {
+    let ggx = Ggx::from_roughness(dielectric.roughness);
+    let ri = 1.0 / vec3::to_f64(dielectric.refraction_index);
+    if wi[2] > 0.0 {
+        let wm = normalize([wo[0] + wi[0], wo[1] + wi[1], wo[2] + wi[2]]);
+        let (d, g2) = ggx_terms(&ggx, wo, wi, wm);
+        let fresnel = vec3::to_f64(microfacet::fresnel_dielectric(
+            dot(wo, wm) as Float,
+            ri as Float,
+        ));
+        return fresnel * d * g2 / (4.0 * wo[2]);
+    }
+
+    let wm = normalize([wo[0] * ri + wi[0], wo[1] * ri + wi[1], wo[2] * ri + wi[2]]);
+    let wm = if wm[2] < 0.0 { wm.map(|c| -c) } else { wm };
+    let (d, g2) = ggx_terms(&ggx, wo, wi, wm);
+    let fresnel = vec3::to_f64(microfacet::fresnel_dielectric(
+        dot(wo, wm) as Float,
+        ri as Float,
+    ));
+    let denom = dot(wi, wm) + dot(wo, wm) * ri;
+    (1.0 - fresnel) * d * g2 * dot(wi, wm).abs() * dot(wo, wm) / (wo[2] * denom * denom)
+}
+
+/// Scatter off `material` many times and compare every weight with what
+/// `f_cos` and `pdf` give for the direction. Returns the fraction of rays
+/// that weren't absorbed.
+fn check_weights(
+    material: &dyn Material,
+    theta: f64,
+    f_cos: impl Fn([f64; 3], [f64; 3]) -> [f64; 3],
+) -> f64 {
+    const SAMPLES: usize = 20_000;
+    let (r_in, record) = hit(material, theta);
+    let wo = local(&-*r_in.direction());
+
+    let mut scattered_count = 0;
+    for _ in 0..SAMPLES {
+        let mut attenuation = Color::ZERO;
+        let mut scattered = Ray::init();
+        if !material.scatter(&r_in, &record, &mut attenuation, &mut scattered) {
+            continue;
+        }
+        scattered_count += 1;
+
+        let pdf = material.pdf(&r_in, &record, &scattered);
+        assert!(pdf > 0.0, "sampled a direction of zero density");
+        let expected = f_cos(wo, local(scattered.direction())).map(|f| f / pdf);
+        for (weight, expected) in attenuation.to_array().into_iter().zip(expected) {
+            assert!(
+                (weight - expected).abs() <= 1e-3 * expected.max(1.0),
+                "weight {weight}, BSDF times cosine over pdf {expected} at {theta}"
+            );
+        }
+    }
+    scattered_count as f64 / SAMPLES as f64
+}
+
+/// Integral of `material.pdf` over the sphere of directions. The grid is
+/// in spherical coordinates, which resolves the narrow lobes near the
+/// poles.
+fn pdf_integral(material: &dyn Material, theta: f64) -> f64 {
+    const N_THETA: usize = 1000;
+    const N_PHI: usize = 400;
+    let (r_in, record) = hit(material, theta);
+    let (d_theta, d_phi) = (PI / N_THETA as f64, 2.0 * PI / N_PHI as f64);
+
+    let mut total = 0.0;
+    for i in 0..N_THETA {
+        let polar = (i as f64 + 0.5) * d_theta;
+        for j in 0..N_PHI {
+            let phi = (j as f64 + 0.5) * d_phi;
+            let direction = Vec3::new(
+                (polar.sin() * phi.cos()) as Float,
+                (polar.sin() * phi.sin()) as Float,
+                polar.cos() as Float,
+            );
+            let pdf = material.pdf(&r_in, &record, &Ray::new(record.p, direction));
+            total += pdf * polar.sin() * d_theta * d_phi;
+        }
+    }
+    total
+}
+
+/// The density is normalized over the directions `scatter` returns, so it
+/// integrates to the fraction of rays that aren't absorbed.
+fn check_pdf(material: &dyn Material, theta: f64, scattered_fraction: f64) {
+    let integral = pdf_integral(material, theta);
+    assert!(
+        integral <= 1.0 + 1e-2,
+        "pdf integrates to {integral} at {theta}"
+    );
+    assert!(
+        (integral - scattered_fraction).abs() < 2e-2,
+        "pdf integrates to {integral}, but {scattered_fraction} of the rays scatter at {theta}"
+    );
+}
+
+#[test]
+fn rough_conductor_weights_match_its_pdf() {
+    util::seed(1);
+    let conductor = Conductor::new(ComplexIor::GOLD, 0.5);
+    for theta in INCIDENT_ANGLES {
+        let scattered = check_weights(&conductor, theta, |wo, wi| {
+            conductor_f_cos(&conductor, wo, wi)
+        });
+        check_pdf(&conductor, theta, scattered);
+    }
+}
+
+#[test]
+fn rough_dielectric_weights_match_its_pdf() {
+    util::seed(2);
+    let dielectric = Dielectric::new(1.5).with_roughness(0.4);
+    for theta in INCIDENT_ANGLES {
+        let scattered = check_weights(&dielectric, theta, |wo, wi| {
+            [dielectric_f_cos(&dielectric, wo, wi); 3]
+        });
+        check_pdf(&dielectric, theta, scattered);
+    }
+}
+
+#[test]
+fn smooth_materials_have_no_density() {
+    let conductor = Conductor::new(ComplexIor::SILVER, 0.0);
+    let dielectric = Dielectric::new(1.5);
+    for material in [&conductor as &dyn Material, &dielectric] {
+        assert_eq!(pdf_integral(material, 0.8), 0.0);
+    }
+}